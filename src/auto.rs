@@ -1,49 +1,165 @@
+use std::fmt;
+use std::sync::Arc;
+
 use log::{debug, info};
 
 use crate::dezoomer::{Dezoomer, DezoomerError, DezoomerInput, ZoomLevel, ZoomLevels};
 use crate::errors::DezoomerError::NeedsData;
 
 pub fn all_dezoomers(include_generic: bool) -> Vec<Box<dyn Dezoomer>> {
-    let mut dezoomers: Vec<Box<dyn Dezoomer>> = vec![
-        Box::<crate::custom_yaml::CustomDezoomer>::default(),
-        Box::<crate::google_arts_and_culture::GAPDezoomer>::default(),
-        Box::<crate::zoomify::ZoomifyDezoomer>::default(),
-        Box::<crate::iiif::IIIF>::default(),
-        Box::<crate::dzi::DziDezoomer>::default(),
-        Box::<crate::generic::GenericDezoomer>::default(),
-        Box::<crate::pff::PFF>::default(),
-        Box::<crate::krpano::KrpanoDezoomer>::default(),
-        Box::<crate::iipimage::IIPImage>::default(),
-        Box::<crate::nypl::NYPLImage>::default(),
-    ];
+    let registry = DezoomerRegistry::default();
+    let mut dezoomers = registry.create_all();
     if include_generic {
-        dezoomers.push(Box::<AutoDezoomer>::default())
+        dezoomers.push(Box::new(AutoDezoomer::new(&registry)))
     }
     dezoomers
 }
+/// Find a dezoomer by its name. The "auto" dezoomer tries all the others.
+pub fn dezoomer_by_name(name: &str) -> Option<Box<dyn Dezoomer>> {
+    DezoomerRegistry::default().by_name(name)
+}
+
+type DezoomerFactory = dyn Fn() -> Box<dyn Dezoomer> + Send + Sync;
+
+#[derive(Clone)]
+struct Registration {
+    name: &'static str,
+    priority: i32,
+    create: Arc<DezoomerFactory>,
+}
+
+/// The dezoomers that can be selected with `--dezoomer`, and that the "auto" dezoomer tries.
+/// Dezoomers with a higher priority are tried first. The built-in dezoomers have
+/// priorities from 1000 to 0, that can be read with [DezoomerRegistry::priority].
+#[derive(Clone)]
+pub struct DezoomerRegistry {
+    /// Sorted by decreasing priority
+    registrations: Vec<Registration>,
+}
+
+impl Default for DezoomerRegistry {
+    fn default() -> Self {
+        let mut registry = DezoomerRegistry {
+            registrations: vec![],
+        };
+        let builtins: [fn() -> Box<dyn Dezoomer>; 11] = [
+            || Box::<crate::custom_yaml::CustomDezoomer>::default(),
+            || Box::<crate::google_arts_and_culture::GAPDezoomer>::default(),
+            || Box::<crate::zoomify::ZoomifyDezoomer>::default(),
+            || Box::<crate::iiif::IIIF>::default(),
+            || Box::<crate::dzi::DziDezoomer>::default(),
+            || Box::<crate::generic::GenericDezoomer>::default(),
+            || Box::<crate::pff::PFF>::default(),
+            || Box::<crate::krpano::KrpanoDezoomer>::default(),
+            || Box::<crate::iipimage::IIPImage>::default(),
+            || Box::<crate::nypl::NYPLImage>::default(),
+            || Box::<crate::tile_list::TileListDezoomer>::default(),
+        ];
+        for (i, create) in builtins.into_iter().enumerate() {
+            registry.register(1000 - 100 * i as i32, create);
+        }
+        registry
+    }
+}
+
+impl DezoomerRegistry {
+    /// Add a dezoomer, created by the given function every time it is used.
+    /// A dezoomer with the same name as an existing one replaces it.
+    /// Dezoomers with the same priority are tried in the order they were registered.
+    pub fn register<F>(&mut self, priority: i32, create: F)
+    where
+        F: Fn() -> Box<dyn Dezoomer> + Send + Sync + 'static,
+    {
+        let name = create().name();
+        self.registrations.retain(|r| r.name != name);
+        let position = self
+            .registrations
+            .iter()
+            .position(|r| r.priority < priority)
+            .unwrap_or(self.registrations.len());
+        let create = Arc::new(create);
+        let registration = Registration {
+            name,
+            priority,
+            create,
+        };
+        self.registrations.insert(position, registration);
+    }
+
+    /// The priority of the dezoomer with the given name
+    pub fn priority(&self, name: &str) -> Option<i32> {
+        self.find(name).map(|r| r.priority)
+    }
+
+    /// The names of the registered dezoomers, in the order they are tried
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.registrations.iter().map(|r| r.name)
+    }
+
+    /// Create all the registered dezoomers, in the order they should be tried
+    pub fn create_all(&self) -> Vec<Box<dyn Dezoomer>> {
+        self.registrations.iter().map(|r| (r.create)()).collect()
+    }
+
+    /// Create the dezoomer with the given name. The "auto" dezoomer tries all the others.
+    pub fn by_name(&self, name: &str) -> Option<Box<dyn Dezoomer>> {
+        if name == "auto" {
+            return Some(Box::new(AutoDezoomer::new(self)));
+        }
+        self.find(name).map(|r| (r.create)())
+    }
+
+    fn find(&self, name: &str) -> Option<&Registration> {
+        self.registrations.iter().find(|r| r.name == name)
+    }
+}
+
+impl fmt::Debug for DezoomerRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
 pub struct AutoDezoomer {
     dezoomers: Vec<Box<dyn Dezoomer>>,
     errors: Vec<(&'static str, DezoomerError)>,
     successes: Vec<ZoomLevel>,
+    found_by: Vec<&'static str>,
     needs_uris: Vec<String>,
 }
 
-impl Default for AutoDezoomer {
-    fn default() -> Self {
+impl AutoDezoomer {
+    /// A dezoomer that tries all the dezoomers of the registry
+    pub fn new(registry: &DezoomerRegistry) -> Self {
         AutoDezoomer {
-            dezoomers: all_dezoomers(false),
+            dezoomers: registry.create_all(),
             errors: vec![],
             successes: vec![],
+            found_by: vec![],
             needs_uris: vec![],
         }
     }
 }
 
+impl Default for AutoDezoomer {
+    fn default() -> Self {
+        AutoDezoomer::new(&DezoomerRegistry::default())
+    }
+}
+
 impl Dezoomer for AutoDezoomer {
     fn name(&self) -> &'static str {
         "auto"
     }
 
+    /// The dezoomer that found the levels, or "auto" when several of them found some
+    fn found_by(&self) -> &'static str {
+        match self.found_by[..] {
+            [name] => name,
+            _ => self.name(),
+        }
+    }
+
     fn zoom_levels(&mut self, data: &DezoomerInput) -> Result<ZoomLevels, DezoomerError> {
         // TO DO: Use drain_filter when it is stabilized
         let mut i = 0;
@@ -57,6 +173,7 @@ impl Dezoomer for AutoDezoomer {
                         levels.len()
                     );
                     self.successes.append(&mut levels);
+                    self.found_by.push(dezoomer.name());
                     false
                 }
                 Err(DezoomerError::NeedsData { uri }) => {
@@ -117,3 +234,38 @@ impl std::fmt::Display for AutoDezoomerError {
         https://github.com/lovasoa/dezoomify-rs/issues")
     }
 }
+
+#[test]
+fn test_registry() {
+    use crate::dezoomer::PageContents;
+
+    #[derive(Default)]
+    struct SiteAdapter;
+
+    impl Dezoomer for SiteAdapter {
+        fn name(&self) -> &'static str {
+            "site_adapter"
+        }
+
+        fn zoom_levels(&mut self, _data: &DezoomerInput) -> Result<ZoomLevels, DezoomerError> {
+            Err(self.wrong_dezoomer())
+        }
+    }
+
+    let mut registry = DezoomerRegistry::default();
+    let before_iiif = registry.priority("iiif").unwrap() + 1;
+    registry.register(before_iiif, || Box::new(SiteAdapter));
+    let names: Vec<_> = registry.names().collect();
+    let position = |name| names.iter().position(|&n| n == name).unwrap();
+    assert_eq!(position("site_adapter") + 1, position("iiif"));
+    assert_eq!(names.len(), 12);
+    assert!(registry.by_name("site_adapter").is_some());
+
+    let mut auto = registry.by_name("auto").unwrap();
+    let input = DezoomerInput {
+        uri: "http://example.com/image".into(),
+        contents: PageContents::Success(vec![]),
+    };
+    let err = auto.zoom_levels(&input).unwrap_err().to_string();
+    assert!(err.contains("site_adapter"), "{}", err);
+}
@@ -0,0 +1,239 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use futures::stream::StreamExt;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+use crate::{dezoomify, Arguments, ZoomError};
+
+/// A single image to download in batch mode
+#[derive(Debug, Deserialize, PartialEq, Eq, Default)]
+pub struct BatchJob {
+    pub url: String,
+    pub outfile: Option<PathBuf>,
+    pub largest: Option<bool>,
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+impl BatchJob {
+    /// The arguments to use to download this image.
+    /// When the job does not say which zoom level to choose, the largest one is downloaded,
+    /// since nothing is asked on the terminal in batch mode.
+    pub fn arguments(&self, args: &Arguments) -> Arguments {
+        let mut job_args = args.clone();
+        job_args.interactive = false;
+        job_args.input_uri = Some(self.url.clone());
+        job_args.outfile = self.outfile.clone();
+        job_args.resume = None;
+        job_args.headers.extend(self.headers.clone());
+        if self.max_width.is_some() || self.max_height.is_some() {
+            job_args.max_width = self.max_width;
+            job_args.max_height = self.max_height;
+            job_args.largest = self.largest.unwrap_or(false);
+        } else {
+            job_args.largest = self.largest.unwrap_or(true) || args.largest;
+        }
+        job_args
+    }
+}
+
+/// Parse a batch file. Each line is either a json object describing a job,
+/// or just the url of an image. Empty lines and lines starting with '#' are ignored.
+pub fn parse_batch(contents: &str) -> Result<Vec<BatchJob>, ZoomError> {
+    contents
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line_number, line)| {
+            if line.starts_with('{') {
+                serde_json::from_str(line).map_err(|source| ZoomError::InvalidBatchJob {
+                    line: line_number,
+                    source,
+                })
+            } else {
+                Ok(BatchJob {
+                    url: line.to_string(),
+                    ..Default::default()
+                })
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchReport {
+    pub successes: usize,
+    pub partial_downloads: usize,
+    pub errors: usize,
+    pub jobs: Vec<JobReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobReport {
+    pub url: String,
+    #[serde(flatten)]
+    pub result: JobResult,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum JobResult {
+    Success {
+        destination: PathBuf,
+    },
+    PartialDownload {
+        destination: String,
+        successful_tiles: u64,
+        total_tiles: u64,
+    },
+    Error {
+        kind: &'static str,
+        message: String,
+    },
+}
+
+impl From<Result<PathBuf, ZoomError>> for JobResult {
+    fn from(result: Result<PathBuf, ZoomError>) -> Self {
+        match result {
+            Ok(destination) => JobResult::Success { destination },
+            Err(ZoomError::PartialDownload {
+                successful_tiles,
+                total_tiles,
+                destination,
+            }) => JobResult::PartialDownload {
+                destination,
+                successful_tiles,
+                total_tiles,
+            },
+            Err(err) => JobResult::Error {
+                kind: err.kind(),
+                message: err.to_string(),
+            },
+        }
+    }
+}
+
+impl BatchReport {
+    fn new(jobs: Vec<JobReport>) -> Self {
+        let count = |f: fn(&JobResult) -> bool| jobs.iter().filter(|j| f(&j.result)).count();
+        BatchReport {
+            successes: count(|r| matches!(r, JobResult::Success { .. })),
+            partial_downloads: count(|r| matches!(r, JobResult::PartialDownload { .. })),
+            errors: count(|r| matches!(r, JobResult::Error { .. })),
+            jobs,
+        }
+    }
+
+    /// The path of the report associated to the given batch file
+    pub fn path_for(batch_file: &Path) -> PathBuf {
+        let mut name = batch_file
+            .file_name()
+            .map(OsString::from)
+            .unwrap_or_default();
+        name.push(".report.json");
+        batch_file.with_file_name(name)
+    }
+}
+
+/// Download all the images listed in a batch file, and write a report next to it.
+/// At most `args.batch_parallelism` images are downloaded at the same time.
+pub async fn dezoomify_batch(
+    args: &Arguments,
+    batch_file: &Path,
+) -> Result<BatchReport, ZoomError> {
+    let jobs = parse_batch(&tokio::fs::read_to_string(batch_file).await?)?;
+    info!("Starting {} jobs from {:?}", jobs.len(), batch_file);
+    let mut reports: Vec<(usize, JobReport)> = futures::stream::iter(jobs.iter().enumerate())
+        .map(|(i, job)| async move {
+            let job_args = job.arguments(args);
+            let result = dezoomify(&job_args).await;
+            debug!("Batch job {} ({}) finished: {:?}", i, job.url, result);
+            let report = JobReport {
+                url: job.url.clone(),
+                result: result.into(),
+            };
+            (i, report)
+        })
+        .buffer_unordered(args.batch_parallelism.max(1))
+        .collect()
+        .await;
+    reports.sort_by_key(|(i, _)| *i);
+    let report = BatchReport::new(reports.into_iter().map(|(_, r)| r).collect());
+    let report_path = BatchReport::path_for(batch_file);
+    info!("Writing the batch report to {:?}", report_path);
+    tokio::fs::write(&report_path, serde_json::to_vec_pretty(&report)?).await?;
+    Ok(report)
+}
+
+#[test]
+fn test_parse_batch() {
+    let contents = r#"
+        # A comment
+        http://example.com/a.dzi
+        {"url": "http://example.com/b.dzi", "outfile": "b.png", "max_width": 1000}
+
+        {"url": "http://example.com/c.dzi", "headers": {"Referer": "http://example.com"}}
+    "#;
+    let jobs = parse_batch(contents).unwrap();
+    assert_eq!(jobs.len(), 3);
+    assert_eq!(jobs[0].url, "http://example.com/a.dzi");
+    assert_eq!(jobs[1].outfile, Some(PathBuf::from("b.png")));
+    assert_eq!(jobs[1].max_width, Some(1000));
+    assert_eq!(jobs[2].headers["Referer"], "http://example.com");
+
+    let args = Arguments::default();
+    let job_args = jobs[1].arguments(&args);
+    assert_eq!(
+        job_args.input_uri.as_deref(),
+        Some("http://example.com/b.dzi")
+    );
+    assert!(!job_args.largest);
+    assert!(jobs[0].arguments(&args).largest);
+    let job_args = jobs[2].arguments(&args);
+    assert!(job_args.headers().any(|(k, _)| k == "Referer"));
+}
+
+#[tokio::test]
+async fn test_batch_job_is_not_interactive() {
+    let dir = tempdir::TempDir::new("dezoomify-rs-batch").unwrap();
+    let job = BatchJob {
+        url: "testdata/zoomify/test_custom_size/ImageProperties.xml".into(),
+        outfile: Some(dir.path().join("out.png")),
+        largest: Some(false),
+        ..Default::default()
+    };
+    let job_args = job.arguments(&Arguments::default());
+    assert!(!job_args.interactive);
+    // No level can be chosen, and none is asked on the terminal
+    let err = dezoomify(&job_args).await.unwrap_err();
+    assert!(matches!(err, ZoomError::NoMatchingLevel { .. }), "{}", err);
+}
+
+#[test]
+fn test_parse_batch_error() {
+    let err = parse_batch("http://a.com\n{\"uri\": 1}").unwrap_err();
+    assert!(matches!(err, ZoomError::InvalidBatchJob { line: 2, .. }));
+}
+
+#[test]
+fn test_job_result() {
+    let err = ZoomError::PartialDownload {
+        successful_tiles: 3,
+        total_tiles: 4,
+        destination: "x.png".into(),
+    };
+    let result: JobResult = Err(err).into();
+    let json = serde_json::to_value(&result).unwrap();
+    assert_eq!(json["status"], "partial_download");
+    assert_eq!(json["successful_tiles"], 3);
+    let result: JobResult = Err(ZoomError::NoTile).into();
+    let json = serde_json::to_value(&result).unwrap();
+    assert_eq!(json["status"], "error");
+    assert_eq!(json["kind"], "no_tile");
+}
@@ -0,0 +1,223 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use image::DynamicImage;
+use url::Url;
+
+use crate::dezoomer::Dezoomer;
+use crate::encoder::{MemoryImage, Output};
+use crate::events::{Event, EventHandler};
+use crate::fetcher::Fetcher;
+use crate::region::Region;
+use crate::{describe, dezoomify, dezoomify_to, Arguments, ImageInfo, ZoomError};
+
+/// How to choose the zoom level to download when an image has several of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LevelSelection {
+    /// The largest zoom level
+    #[default]
+    Largest,
+    /// The largest zoom level that fits in the given size.
+    /// The job fails if none of the levels fits.
+    Fit {
+        max_width: Option<u32>,
+        max_height: Option<u32>,
+    },
+}
+
+/// Configures a [Job], to use dezoomify-rs as a library.
+/// Jobs never read from the standard input nor write to the standard output.
+///
+/// ```no_run
+/// # async fn run() -> Result<(), dezoomify_rs::ZoomError> {
+/// use dezoomify_rs::DezoomifyBuilder;
+///
+/// let job = DezoomifyBuilder::new("https://example.com/iiif/image/info.json")
+///     .output_file("image.jpg")
+///     .parallelism(4)
+///     .on_event(|event| eprintln!("{:?}", event))
+///     .build();
+/// let saved_as = job.run().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct DezoomifyBuilder {
+    args: Arguments,
+}
+
+impl DezoomifyBuilder {
+    /// Dezoom the image at the given URL or local path
+    pub fn new(source: impl Into<String>) -> Self {
+        let args = Arguments {
+            input_uri: Some(source.into()),
+            largest: true,
+            interactive: false,
+            ..Arguments::default()
+        };
+        DezoomifyBuilder { args }
+    }
+
+    /// The name of the dezoomer to use, instead of detecting it automatically
+    pub fn dezoomer(mut self, name: impl Into<String>) -> Self {
+        self.args.dezoomer = name.into();
+        self
+    }
+
+    pub fn level(mut self, selection: LevelSelection) -> Self {
+        match selection {
+            LevelSelection::Largest => {
+                self.args.largest = true;
+                self.args.max_width = None;
+                self.args.max_height = None;
+            }
+            LevelSelection::Fit {
+                max_width,
+                max_height,
+            } => {
+                self.args.largest = false;
+                self.args.max_width = max_width;
+                self.args.max_height = max_height;
+            }
+        }
+        self
+    }
+
+    /// Download only a part of the image
+    pub fn region(mut self, region: Region) -> Self {
+        self.args.region = Some(region);
+        self
+    }
+
+    /// Where [Job::run] saves the image. Its format is chosen from the extension.
+    /// By default, a name is generated from the image metadata, in the current directory.
+    pub fn output_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.args.outfile = Some(path.into());
+        self
+    }
+
+    /// See `--compression`
+    pub fn compression(mut self, compression: u8) -> Self {
+        self.args.compression = compression;
+        self
+    }
+
+    /// Add an http header to all the requests
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.args.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// The maximum number of tiles downloaded at the same time
+    pub fn parallelism(mut self, parallelism: usize) -> Self {
+        self.args.parallelism = parallelism;
+        self
+    }
+
+    /// The number of new attempts to make when a tile fails to download
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.args.retries = retries;
+        self
+    }
+
+    pub fn retry_delay(mut self, delay: Duration) -> Self {
+        self.args.retry_delay = delay;
+        self
+    }
+
+    /// The minimum time between two requests to the same server
+    pub fn min_interval(mut self, interval: Duration) -> Self {
+        self.args.min_interval = interval;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.args.timeout = timeout;
+        self
+    }
+
+    pub fn proxy(mut self, proxy: Url) -> Self {
+        self.args.proxy = Some(proxy);
+        self
+    }
+
+    pub fn accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.args.accept_invalid_certs = accept;
+        self
+    }
+
+    /// See `--tile-cache`
+    pub fn tile_cache(mut self, folder: impl Into<PathBuf>) -> Self {
+        self.args.tile_storage_folder = Some(folder.into());
+        self
+    }
+
+    /// Make the http requests with the given fetcher instead of the default reqwest client
+    pub fn fetcher(mut self, fetcher: Arc<dyn Fetcher>) -> Self {
+        self.args.fetcher = Some(fetcher);
+        self
+    }
+
+    /// Make a dezoomer available, both for [DezoomifyBuilder::dezoomer] and for the automatic detection.
+    /// Dezoomers with a higher priority are tried first: use [DezoomerRegistry::priority](crate::DezoomerRegistry::priority)
+    /// to place it relative to a built-in dezoomer.
+    pub fn register_dezoomer<F>(mut self, priority: i32, create: F) -> Self
+    where
+        F: Fn() -> Box<dyn Dezoomer> + Send + Sync + 'static,
+    {
+        self.args.dezoomers.register(priority, create);
+        self
+    }
+
+    /// Call the given function with every event of the download
+    pub fn on_event<F: Fn(&Event) + Send + Sync + 'static>(mut self, handler: F) -> Self {
+        self.args.event_handler = Some(EventHandler::new(handler));
+        self
+    }
+
+    pub fn build(self) -> Job {
+        Job { args: self.args }
+    }
+}
+
+/// A configured image download
+#[derive(Debug, Clone)]
+pub struct Job {
+    args: Arguments,
+}
+
+impl Job {
+    /// Download the image, and return the path where it was saved
+    pub async fn run(&self) -> Result<PathBuf, ZoomError> {
+        dezoomify(&self.args).await
+    }
+
+    /// Download the image and return it, without writing any file.
+    /// When some tiles could not be downloaded, the rest of the image is returned
+    /// together with the [ZoomError::PartialDownload] error.
+    pub async fn run_to_image(&self) -> Result<(DynamicImage, Option<ZoomError>), ZoomError> {
+        let memory = MemoryImage::default();
+        let partial = match dezoomify_to(&self.args, Output::Memory(memory.clone())).await {
+            Ok(()) => None,
+            Err(err @ ZoomError::PartialDownload { .. }) => Some(err),
+            Err(err) => return Err(err),
+        };
+        let image = memory
+            .take()
+            .expect("The image should be available when the download finishes");
+        Ok((image, partial))
+    }
+
+    /// Download the image to the given output, such as a [PixelWriter](crate::PixelWriter).
+    /// When some tiles could not be downloaded, a [ZoomError::PartialDownload] is returned,
+    /// but the rest of the image is still written to the output.
+    pub async fn run_to(&self, output: Output) -> Result<(), ZoomError> {
+        dezoomify_to(&self.args, output).await
+    }
+
+    /// List the zoom levels of the image, without downloading it
+    pub async fn describe(&self) -> Result<ImageInfo, ZoomError> {
+        describe(&self.args).await
+    }
+}
@@ -0,0 +1,741 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::future::Future;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+use crate::network::{fetch_uri_if_modified, is_remote, HttpClient, Validators};
+use crate::ZoomError;
+
+const INDEX_FILE: &str = "index.jsonl";
+
+/// Information about a file stored in the cache
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub url: String,
+    /// The http headers that were used to request the file, and that may change its contents
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// The image the file belongs to
+    pub image: Option<String>,
+    pub size: u64,
+    /// When the file was stored, in seconds since the unix epoch
+    pub created: u64,
+    /// When the file was last read or written, in seconds since the unix epoch
+    pub last_used: u64,
+    /// The ETag header of the response, used to check whether the file has changed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    /// The Last-Modified header of the response, used to check whether the file has changed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+}
+
+/// A line of the index file. While downloading, records are only appended to the index.
+/// It is rewritten with only the current entries by `CacheStore::gc`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum IndexRecord {
+    Put { key: String, entry: CacheEntry },
+    Use { key: String, time: u64 },
+    Remove { key: String },
+}
+
+/// A folder containing downloaded files named after a hash of their url,
+/// and an index that describes them.
+/// When the total size of the files exceeds `max_size`,
+/// the least recently used ones are removed.
+#[derive(Debug)]
+pub struct CacheStore {
+    root: PathBuf,
+    max_size: Option<u64>,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Debug)]
+struct CacheState {
+    entries: HashMap<String, CacheEntry>,
+    total_size: u64,
+    index: File,
+}
+
+/// The files of an image that are stored in the cache
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct CachedImage {
+    /// The URI of the image, if it is known
+    pub image: Option<String>,
+    pub files: u64,
+    pub size: u64,
+    /// The last time one of the files of the image was used, in seconds since the unix epoch
+    pub last_used: u64,
+}
+
+/// The result of a cache cleanup
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct GcReport {
+    pub removed_files: u64,
+    pub removed_bytes: u64,
+    pub remaining_files: u64,
+    pub remaining_bytes: u64,
+}
+
+impl CacheStore {
+    pub fn open(root: &Path, max_size: Option<u64>) -> Result<Self, ZoomError> {
+        fs::create_dir_all(root)?;
+        let index_path = root.join(INDEX_FILE);
+        let mut entries = HashMap::new();
+        match File::open(&index_path) {
+            Ok(file) => {
+                for (i, line) in BufReader::new(file).lines().enumerate() {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    match serde_json::from_str(&line) {
+                        Ok(record) => apply(&mut entries, record),
+                        Err(e) => warn!("Ignoring line {} of {:?}: {}", i + 1, index_path, e),
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        debug!("Opened the cache {:?} with {} entries", root, entries.len());
+        let total_size = entries.values().map(|e| e.size).sum();
+        let index = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&index_path)?;
+        Ok(CacheStore {
+            root: root.to_path_buf(),
+            max_size,
+            state: Mutex::new(CacheState {
+                entries,
+                total_size,
+                index,
+            }),
+        })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(&key[..2]).join(key)
+    }
+
+    /// All the files in the cache, with the most recently used first
+    pub fn entries(&self) -> Vec<CacheEntry> {
+        let state = self.state.lock().unwrap();
+        let mut entries: Vec<CacheEntry> = state.entries.values().cloned().collect();
+        entries.sort_by(|a, b| b.last_used.cmp(&a.last_used).then(a.url.cmp(&b.url)));
+        entries
+    }
+
+    /// A summary of the files in the cache for each image, with the most recently used first
+    pub fn images(&self) -> Vec<CachedImage> {
+        let mut images: BTreeMap<Option<String>, CachedImage> = BTreeMap::new();
+        for entry in self.entries() {
+            let image = images
+                .entry(entry.image.clone())
+                .or_insert_with(|| CachedImage {
+                    image: entry.image.clone(),
+                    files: 0,
+                    size: 0,
+                    last_used: entry.last_used,
+                });
+            image.files += 1;
+            image.size += entry.size;
+        }
+        let mut images: Vec<CachedImage> = images.into_values().collect();
+        images.sort_by_key(|image| std::cmp::Reverse(image.last_used));
+        images
+    }
+
+    pub async fn read(&self, key: &str) -> Option<(Vec<u8>, CacheEntry)> {
+        if !self.state.lock().unwrap().entries.contains_key(key) {
+            return None;
+        }
+        match tokio::fs::read(self.path(key)).await {
+            Ok(contents) => {
+                let mut state = self.state.lock().unwrap();
+                let time = now();
+                let entry = state.entries.get_mut(key)?;
+                entry.last_used = time;
+                let entry = entry.clone();
+                let key = key.to_string();
+                state.append(&IndexRecord::Use { key, time });
+                Some((contents, entry))
+            }
+            Err(e) => {
+                debug!("Unable to read the cached file {}: {}", key, e);
+                let mut state = self.state.lock().unwrap();
+                state.remove(key);
+                None
+            }
+        }
+    }
+
+    pub async fn write(
+        &self,
+        key: &str,
+        entry: CacheEntry,
+        contents: &[u8],
+    ) -> Result<(), ZoomError> {
+        let path = self.path(key);
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        tokio::fs::write(&path, contents).await?;
+        let evicted = {
+            let mut state = self.state.lock().unwrap();
+            state.remove(key);
+            state.total_size += entry.size;
+            let record = IndexRecord::Put {
+                key: key.to_string(),
+                entry: entry.clone(),
+            };
+            state.append(&record);
+            state.entries.insert(key.to_string(), entry);
+            match self.max_size {
+                Some(max_size) if state.total_size > max_size => {
+                    // Free some more space than needed, so that we don't have to evict
+                    // files again when the next one is written
+                    let target = max_size - max_size / 10;
+                    self.evict(&mut state, target, Some(key))
+                }
+                _ => vec![],
+            }
+        };
+        // The files are deleted without holding the lock, so that other downloads don't wait
+        for (path, _) in evicted {
+            if let Err(e) = tokio::fs::remove_file(&path).await {
+                debug!("Unable to remove {:?}: {}", path, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the least recently used entries from the index
+    /// until the cache is not larger than `target` bytes.
+    /// Returns the paths and sizes of the files of the removed entries, which the caller deletes.
+    fn evict(
+        &self,
+        state: &mut CacheState,
+        target: u64,
+        keep: Option<&str>,
+    ) -> Vec<(PathBuf, u64)> {
+        let mut by_age: Vec<(u64, String)> = state
+            .entries
+            .iter()
+            .filter(|(key, _)| Some(key.as_str()) != keep)
+            .map(|(key, entry)| (entry.last_used, key.clone()))
+            .collect();
+        by_age.sort();
+        let mut evicted = vec![];
+        for (_, key) in by_age {
+            if state.total_size <= target {
+                break;
+            }
+            if let Some(entry) = state.remove(&key) {
+                debug!("Evicting {} from the cache", entry.url);
+                evicted.push((self.path(&key), entry.size));
+            }
+        }
+        evicted
+    }
+
+    /// Remove the cached files that are not in the index, the entries whose file is missing,
+    /// and the least recently used files if the cache is larger than its maximal size.
+    /// The index is then rewritten with only the remaining entries.
+    pub fn gc(&self) -> Result<GcReport, ZoomError> {
+        let mut state = self.state.lock().unwrap();
+        let mut report = GcReport::default();
+
+        let missing: Vec<String> = state
+            .entries
+            .keys()
+            .filter(|key| !self.path(key).exists())
+            .cloned()
+            .collect();
+        for key in missing {
+            state.remove(&key);
+        }
+
+        if let Some(max_size) = self.max_size {
+            for (path, size) in self.evict(&mut state, max_size, None) {
+                if let Err(e) = fs::remove_file(&path) {
+                    debug!("Unable to remove {:?}: {}", path, e);
+                }
+                report.removed_files += 1;
+                report.removed_bytes += size;
+            }
+        }
+
+        // Only the files that follow the layout of the cache are removed:
+        // the folder may contain other files that do not belong to us
+        for dir_entry in fs::read_dir(&self.root)? {
+            let dir_entry = dir_entry?;
+            let dir_name = dir_entry.file_name().to_string_lossy().to_string();
+            if !dir_entry.file_type()?.is_dir() || !is_key_prefix(&dir_name) {
+                continue;
+            }
+            for file in fs::read_dir(dir_entry.path())? {
+                let file = file?;
+                let key = file.file_name().to_string_lossy().to_string();
+                if file.file_type()?.is_file()
+                    && is_key(&key)
+                    && key.starts_with(&dir_name)
+                    && !state.entries.contains_key(&key)
+                {
+                    report.add_removed(&file.path())?;
+                }
+            }
+            // Fails if the directory is not empty
+            let _ = fs::remove_dir(dir_entry.path());
+        }
+
+        let index_path = self.root.join(INDEX_FILE);
+        let tmp_path = self.root.join(format!("{}.tmp", INDEX_FILE));
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        for (key, entry) in state.entries.iter() {
+            let record = IndexRecord::Put {
+                key: key.clone(),
+                entry: entry.clone(),
+            };
+            serde_json::to_writer(&mut writer, &record)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&tmp_path, &index_path)?;
+        state.index = OpenOptions::new().append(true).open(&index_path)?;
+
+        report.remaining_files = state.entries.len() as u64;
+        report.remaining_bytes = state.total_size;
+        info!("Cleaned the cache {:?}: {:?}", self.root, report);
+        Ok(report)
+    }
+}
+
+impl CacheState {
+    fn append(&mut self, record: &IndexRecord) {
+        let mut line = serde_json::to_vec(record).expect("index records are serializable");
+        line.push(b'\n');
+        if let Err(e) = self.index.write_all(&line) {
+            warn!("Unable to write to the cache index: {}", e);
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> Option<CacheEntry> {
+        let entry = self.entries.remove(key)?;
+        self.total_size -= entry.size;
+        self.append(&IndexRecord::Remove {
+            key: key.to_string(),
+        });
+        Some(entry)
+    }
+}
+
+impl GcReport {
+    fn add_removed(&mut self, path: &Path) -> Result<(), ZoomError> {
+        let size = fs::metadata(path)?.len();
+        debug!("Removing {:?} from the cache", path);
+        fs::remove_file(path)?;
+        self.removed_files += 1;
+        self.removed_bytes += size;
+        Ok(())
+    }
+}
+
+fn apply(entries: &mut HashMap<String, CacheEntry>, record: IndexRecord) {
+    match record {
+        IndexRecord::Put { key, entry } => {
+            entries.insert(key, entry);
+        }
+        IndexRecord::Use { key, time } => {
+            if let Some(entry) = entries.get_mut(&key) {
+                entry.last_used = time;
+            }
+        }
+        IndexRecord::Remove { key } => {
+            entries.remove(&key);
+        }
+    }
+}
+
+/// Whether a file name is a cache key: the hexadecimal sha1 hash of a url
+fn is_key(name: &str) -> bool {
+    name.len() == 40 && is_lower_hex(name)
+}
+
+/// Whether a folder name is the prefix of the cache keys of the files it contains
+fn is_key_prefix(name: &str) -> bool {
+    name.len() == 2 && is_lower_hex(name)
+}
+
+fn is_lower_hex(s: &str) -> bool {
+    s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Access to the cache for the files of a given image.
+/// In offline mode, files that are not in the cache are never requested from the network.
+#[derive(Debug, Clone, Default)]
+pub struct FileCache {
+    store: Option<Arc<CacheStore>>,
+    pub offline: bool,
+    /// The image the cached files belong to
+    image: Option<String>,
+    /// The headers that are sent with the requests, and are part of the cache keys
+    headers: BTreeMap<String, String>,
+}
+
+/// The cache stores opened by all the downloads made with the same arguments.
+/// A cache folder is opened only once, so that all the downloads that use it share its index.
+#[derive(Debug, Clone, Default)]
+pub struct SharedCacheStores {
+    stores: Arc<Mutex<HashMap<PathBuf, Arc<CacheStore>>>>,
+}
+
+impl SharedCacheStores {
+    pub fn get(&self, root: &Path, max_size: Option<u64>) -> Result<Arc<CacheStore>, ZoomError> {
+        fs::create_dir_all(root)?;
+        let root = root.canonicalize()?;
+        let mut stores = self.stores.lock().unwrap();
+        if let Some(store) = stores.get(&root) {
+            return Ok(Arc::clone(store));
+        }
+        let store = Arc::new(CacheStore::open(&root, max_size)?);
+        stores.insert(root, Arc::clone(&store));
+        Ok(store)
+    }
+}
+
+impl FileCache {
+    pub fn new(store: Option<Arc<CacheStore>>, offline: bool) -> Self {
+        FileCache {
+            store,
+            offline,
+            ..Default::default()
+        }
+    }
+
+    /// Set the image to which the cached files belong, and the http headers used to request them
+    pub fn for_image<'a, I: Iterator<Item = (&'a String, &'a String)>>(
+        mut self,
+        image: &str,
+        headers: I,
+    ) -> Self {
+        self.image = Some(image.to_string());
+        self.headers = headers.map(|(k, v)| (k.clone(), v.clone())).collect();
+        self
+    }
+
+    fn key(&self, uri: &str) -> String {
+        let mut hasher = Sha1::new();
+        hasher.update(uri.as_bytes());
+        for (name, value) in &self.headers {
+            hasher.update(format!("\n{}: {}", name.to_lowercase(), value).as_bytes());
+        }
+        hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    /// Fetch a file from the cache, or from its uri if it is not cached yet
+    pub async fn fetch(&self, uri: &str, http: &HttpClient) -> Result<Vec<u8>, ZoomError> {
+        self.get_or_download(uri, |validators| async move {
+            fetch_uri_if_modified(uri, http, &validators).await
+        })
+        .await
+    }
+
+    /// Get a file from the cache, or download it with the given function.
+    /// When the cached file has validators, it is passed to the download function,
+    /// which returns None if the file has not changed since it was cached.
+    /// Only remote files are cached.
+    /// Returning None when nothing is cached gives a [ZoomError::HttpStatus] error.
+    pub async fn get_or_download<F, Fut>(
+        &self,
+        uri: &str,
+        download: F,
+    ) -> Result<Vec<u8>, ZoomError>
+    where
+        F: FnOnce(Validators) -> Fut,
+        Fut: Future<Output = Result<Option<(Vec<u8>, Validators)>, ZoomError>>,
+    {
+        if !is_remote(uri) {
+            return download(Validators::default())
+                .await
+                .map(|fetched| fetched.map(|(contents, _)| contents).unwrap_or_default());
+        }
+        let cached = self.read(uri).await;
+        let validators = match cached {
+            Some((contents, validators)) if self.offline || validators.is_empty() => {
+                return Ok(contents)
+            }
+            Some((_, ref validators)) => validators.clone(),
+            None => {
+                self.check_online(uri)?;
+                Validators::default()
+            }
+        };
+        match (download(validators.clone()).await, cached) {
+            (Ok(Some((contents, validators))), _) => {
+                self.write(uri, &contents, validators).await;
+                Ok(contents)
+            }
+            (Ok(None), Some((contents, _))) => {
+                debug!("The cached version of {} is up to date", uri);
+                Ok(contents)
+            }
+            (Err(e), Some((contents, _))) => {
+                warn!("Unable to check whether {} has changed: {}", uri, e);
+                Ok(contents)
+            }
+            (Ok(None), None) => {
+                // The server answered "Not Modified" although nothing was cached
+                Err(ZoomError::HttpStatus {
+                    status: 304,
+                    uri: uri.to_string(),
+                })
+            }
+            (Err(e), None) => Err(e),
+        }
+    }
+
+    /// Fail if the given uri would have to be downloaded in offline mode
+    pub fn check_online(&self, uri: &str) -> Result<(), ZoomError> {
+        if self.offline && is_remote(uri) {
+            return Err(ZoomError::Offline {
+                uri: uri.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    pub async fn write(&self, uri: &str, contents: &[u8], validators: Validators) {
+        if let Some(store) = &self.store {
+            let time = now();
+            let entry = CacheEntry {
+                url: uri.to_string(),
+                headers: self.headers.clone(),
+                image: self.image.clone(),
+                size: contents.len() as u64,
+                created: time,
+                last_used: time,
+                etag: validators.etag,
+                last_modified: validators.last_modified,
+            };
+            match store.write(&self.key(uri), entry, contents).await {
+                Ok(()) => debug!("Wrote {} to cache ({} bytes)", uri, contents.len()),
+                Err(e) => warn!(
+                    "Unable to write {} to the cache {:?}: {}",
+                    uri, store.root, e
+                ),
+            }
+        }
+    }
+
+    /// Read a file from the cache, with the validators that allow checking whether it changed
+    pub async fn read(&self, uri: &str) -> Option<(Vec<u8>, Validators)> {
+        let (contents, entry) = self.store.as_ref()?.read(&self.key(uri)).await?;
+        debug!("{} read from cache", uri);
+        let validators = Validators {
+            etag: entry.etag,
+            last_modified: entry.last_modified,
+        };
+        Some((contents, validators))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache_dir(name: &str) -> tempdir::TempDir {
+        tempdir::TempDir::new(name).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_offline_cache() {
+        let root = cache_dir("dezoomify-rs-offline-cache");
+        let store = CacheStore::open(root.path(), None).unwrap();
+        let cache = FileCache::new(Some(Arc::new(store)), true);
+        let http = HttpClient::from(reqwest::Client::new());
+        let uri = "http://example.com/info.json";
+        let err = cache.fetch(uri, &http).await.unwrap_err();
+        assert!(matches!(err, ZoomError::Offline { .. }), "{}", err);
+        cache.write(uri, b"{}", Validators::default()).await;
+        assert_eq!(cache.fetch(uri, &http).await.unwrap(), b"{}");
+    }
+
+    #[tokio::test]
+    async fn test_cache_keys_and_index() {
+        let root = cache_dir("dezoomify-rs-cache-index");
+        let referer = [("Referer".to_string(), "http://a.com".to_string())];
+        let stores = SharedCacheStores::default();
+        let store = stores.get(root.path(), None).unwrap();
+        let cache = FileCache::new(Some(store), false).for_image(
+            "http://a.com/info.json",
+            referer.iter().map(|(k, v)| (k, v)),
+        );
+        let uri = "http://a.com/tile.jpg";
+        cache.write(uri, b"tile", Validators::default()).await;
+        let other_headers = FileCache::new(Some(stores.get(root.path(), None).unwrap()), false);
+        assert_eq!(other_headers.read(uri).await, None);
+
+        // The index is loaded when the cache is opened again
+        let store = CacheStore::open(root.path(), None).unwrap();
+        let entries = store.entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].url, uri);
+        assert_eq!(entries[0].image.as_deref(), Some("http://a.com/info.json"));
+        assert_eq!(entries[0].headers["Referer"], "http://a.com");
+        assert_eq!(cache.read(uri).await.unwrap().0, b"tile");
+    }
+
+    #[tokio::test]
+    async fn test_cache_revalidation() {
+        let root = cache_dir("dezoomify-rs-cache-revalidation");
+        let store = CacheStore::open(root.path(), None).unwrap();
+        let cache = FileCache::new(Some(Arc::new(store)), false);
+        let uri = "http://example.com/tile.jpg";
+        let etag = Validators {
+            etag: Some("\"v1\"".into()),
+            last_modified: None,
+        };
+        let first = cache
+            .get_or_download(uri, |validators| async move {
+                assert!(validators.is_empty());
+                Ok(Some((b"v1".to_vec(), etag)))
+            })
+            .await
+            .unwrap();
+        assert_eq!(first, b"v1");
+        // The server answers 304 Not Modified: the cached file is used
+        let not_modified = cache
+            .get_or_download(uri, |validators| async move {
+                assert_eq!(validators.etag.as_deref(), Some("\"v1\""));
+                Ok(None)
+            })
+            .await
+            .unwrap();
+        assert_eq!(not_modified, b"v1");
+        // The file changed on the server: the cache is updated
+        let changed = cache
+            .get_or_download(uri, |_| async {
+                Ok(Some((b"v2".to_vec(), Validators::default())))
+            })
+            .await
+            .unwrap();
+        assert_eq!(changed, b"v2");
+        assert_eq!(
+            cache.read(uri).await.unwrap(),
+            (b"v2".to_vec(), Validators::default())
+        );
+        // A 304 Not Modified answer is an error when nothing was cached
+        let uncached = "http://example.com/other.jpg";
+        let err = cache
+            .get_or_download(uncached, |_| async { Ok(None) })
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, ZoomError::HttpStatus { status: 304, uri } if uri == uncached),
+            "{}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cache_eviction_and_gc() {
+        let root = cache_dir("dezoomify-rs-cache-gc");
+        let store = CacheStore::open(root.path(), Some(25)).unwrap();
+        let entry = |url: &str, last_used: u64| CacheEntry {
+            url: url.to_string(),
+            headers: BTreeMap::new(),
+            image: None,
+            size: 10,
+            created: 0,
+            last_used,
+            etag: None,
+            last_modified: None,
+        };
+        store.write("aa01", entry("a", 1), &[0; 10]).await.unwrap();
+        store.write("bb02", entry("b", 3), &[0; 10]).await.unwrap();
+        // Writing a third file makes the cache larger than 25 bytes
+        store.write("cc03", entry("c", 2), &[0; 10]).await.unwrap();
+        let urls: Vec<String> = store.entries().into_iter().map(|e| e.url).collect();
+        assert_eq!(urls, vec!["b", "c"]);
+        assert_eq!(
+            store.images(),
+            vec![CachedImage {
+                image: None,
+                files: 2,
+                size: 20,
+                last_used: 3
+            }]
+        );
+        assert!(!root.path().join("aa").join("aa01").exists());
+
+        let orphan = root.path().join("ab").join(format!("ab{}", "0".repeat(38)));
+        fs::create_dir_all(orphan.parent().unwrap()).unwrap();
+        fs::write(&orphan, [0; 5]).unwrap();
+        let report = store.gc().unwrap();
+        assert!(!orphan.exists());
+        assert_eq!(
+            report,
+            GcReport {
+                removed_files: 1,
+                removed_bytes: 5,
+                remaining_files: 2,
+                remaining_bytes: 20,
+            }
+        );
+        let reopened = CacheStore::open(root.path(), None).unwrap();
+        assert_eq!(reopened.entries(), store.entries());
+    }
+
+    #[tokio::test]
+    async fn test_gc_keeps_foreign_files() {
+        let root = cache_dir("dezoomify-rs-cache-foreign");
+        let store = CacheStore::open(root.path(), None).unwrap();
+        // The cache folder may be a folder that contains other files, such as the current directory
+        let foreign = [
+            root.path().join("photo.jpg"),
+            root.path().join("ab").join("notes.txt"),
+            root.path()
+                .join("documents")
+                .join(format!("do{}", "0".repeat(38))),
+        ];
+        for path in &foreign {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"precious").unwrap();
+        }
+        assert_eq!(store.gc().unwrap().removed_files, 0);
+        for path in &foreign {
+            assert!(path.exists(), "{:?} was removed", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shared_stores() {
+        let root = cache_dir("dezoomify-rs-cache-shared");
+        let stores = SharedCacheStores::default();
+        let first = stores.get(root.path(), None).unwrap();
+        let second = stores.clone().get(&root.path().join("."), None).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+    }
+}
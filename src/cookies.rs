@@ -0,0 +1,102 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::debug;
+use reqwest::cookie::Jar;
+use url::Url;
+
+use crate::ZoomError;
+
+/// Cookies shared by all the http clients created from the same arguments,
+/// so that a session cookie set while loading the metadata of an image
+/// is sent with the requests for its tiles.
+#[derive(Debug, Clone, Default)]
+pub struct SharedCookies {
+    jar: Arc<Jar>,
+    imported: Arc<Mutex<bool>>,
+}
+
+impl SharedCookies {
+    /// The cookie jar. The cookies from the given file are added to it the first time it is used.
+    pub fn jar(&self, cookie_file: Option<&Path>) -> Result<Arc<Jar>, ZoomError> {
+        let mut imported = self.imported.lock().unwrap();
+        if !*imported {
+            if let Some(path) = cookie_file {
+                debug!("Loading cookies from {:?}", path);
+                import_netscape_cookies(&self.jar, &std::fs::read_to_string(path)?)?;
+            }
+            *imported = true;
+        }
+        Ok(Arc::clone(&self.jar))
+    }
+}
+
+/// Add the cookies from a file in the Netscape format, as exported by browser extensions.
+/// Each line has seven tab-separated fields:
+/// domain, include subdomains, path, secure, expiration timestamp, name and value.
+fn import_netscape_cookies(jar: &Jar, contents: &str) -> Result<(), ZoomError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    for (i, line) in contents.lines().enumerate() {
+        // Cookies with the HttpOnly flag are prefixed with "#HttpOnly_"
+        let line = line.strip_prefix("#HttpOnly_").unwrap_or(line).trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let malformed = || ZoomError::MalformedCookieFile { line: i + 1 };
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [domain, include_subdomains, path, secure, expires, name, value] = fields[..] else {
+            return Err(malformed());
+        };
+        let expires: u64 = expires.parse().map_err(|_| malformed())?;
+        if expires != 0 && expires < now {
+            continue;
+        }
+        let host = domain.trim_start_matches('.');
+        let url = Url::parse(&format!("https://{}{}", host, path)).map_err(|_| malformed())?;
+        let mut cookie = format!("{}={}; Path={}", name, value, path);
+        if include_subdomains.eq_ignore_ascii_case("TRUE") {
+            cookie.push_str("; Domain=");
+            cookie.push_str(host);
+        }
+        if secure.eq_ignore_ascii_case("TRUE") {
+            cookie.push_str("; Secure");
+        }
+        jar.add_cookie_str(&cookie, &url);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_import_netscape_cookies() {
+    use reqwest::cookie::CookieStore;
+
+    let contents = "# Netscape HTTP Cookie File\n\
+        .example.com\tTRUE\t/\tFALSE\t0\tsession\tabc\n\
+        #HttpOnly_images.example.com\tFALSE\t/tiles\tTRUE\t0\ttoken\txyz\n\
+        .example.com\tTRUE\t/\tFALSE\t1\texpired\t1\n";
+    let jar = Jar::default();
+    import_netscape_cookies(&jar, contents).unwrap();
+    let cookies = |url: &str| {
+        jar.cookies(&Url::parse(url).unwrap()).map(|v| {
+            let mut cookies: Vec<&str> = v.to_str().unwrap().split("; ").collect();
+            cookies.sort();
+            cookies.join("; ")
+        })
+    };
+    assert_eq!(
+        cookies("https://images.example.com/tiles/0.jpg").as_deref(),
+        Some("session=abc; token=xyz")
+    );
+    assert_eq!(
+        cookies("http://www.example.com/").as_deref(),
+        Some("session=abc")
+    );
+    assert_eq!(cookies("http://other.com/"), None);
+
+    let err = import_netscape_cookies(&jar, "example.com\tTRUE\t/").unwrap_err();
+    assert!(matches!(err, ZoomError::MalformedCookieFile { line: 1 }));
+}
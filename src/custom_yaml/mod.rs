@@ -71,6 +71,10 @@ impl TileProvider for CustomYamlTiles {
         }
     }
 
+    fn tile_count_hint(&self) -> Option<u64> {
+        Some(self.tile_set.into_iter().count() as u64)
+    }
+
     fn http_headers(&self) -> HashMap<String, String> {
         self.headers.clone()
     }
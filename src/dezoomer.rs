@@ -3,6 +3,7 @@ use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::Debug;
 use std::str::FromStr;
+use std::sync::Arc;
 
 pub use crate::errors::DezoomerError;
 
@@ -44,7 +45,7 @@ pub struct DezoomerInputWithContents<'a> {
 }
 
 impl DezoomerInput {
-    pub fn with_contents(&self) -> Result<DezoomerInputWithContents, DezoomerError> {
+    pub fn with_contents(&self) -> Result<DezoomerInputWithContents<'_>, DezoomerError> {
         match &self.contents {
             PageContents::Unknown => Err(DezoomerError::NeedsData {
                 uri: self.uri.clone(),
@@ -85,6 +86,12 @@ pub trait Dezoomer {
 
     /// List of the various sizes at which an image is available
     fn zoom_levels(&mut self, data: &DezoomerInput) -> Result<ZoomLevels, DezoomerError>;
+
+    /// The name of the dezoomer that found the zoom levels,
+    /// for dezoomers that delegate to others
+    fn found_by(&self) -> &'static str {
+        self.name()
+    }
     fn assert(&self, c: bool) -> Result<(), DezoomerError> {
         if c {
             Ok(())
@@ -113,15 +120,48 @@ impl TileFetchResult {
     }
 }
 
-type PostProcessResult = Result<Vec<u8>, Box<dyn Error + Send>>;
-// TODO : fix
-// see: https://github.com/rust-lang/rust/issues/63033
-#[derive(Clone, Copy)]
+pub type PostProcessResult = Result<Vec<u8>, Box<dyn Error + Send>>;
+type PostProcess = dyn Fn(&TileReference, Vec<u8>) -> PostProcessResult + Send + Sync;
+
+/// Transforms the bytes of a downloaded tile before they are decoded.
+/// The function can capture state that is specific to an image, such as a decryption key.
+#[derive(Clone, Default)]
 pub enum PostProcessFn {
-    Fn(fn(&TileReference, Vec<u8>) -> PostProcessResult),
+    Fn(Arc<PostProcess>),
+    #[default]
     None,
 }
 
+impl PostProcessFn {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&TileReference, Vec<u8>) -> PostProcessResult + Send + Sync + 'static,
+    {
+        PostProcessFn::Fn(Arc::new(f))
+    }
+
+    pub fn is_none(&self) -> bool {
+        matches!(self, PostProcessFn::None)
+    }
+
+    /// Apply the function to the bytes of a tile, or return them unchanged
+    pub fn apply(&self, tile: &TileReference, bytes: Vec<u8>) -> PostProcessResult {
+        match self {
+            PostProcessFn::Fn(f) => f(tile, bytes),
+            PostProcessFn::None => Ok(bytes),
+        }
+    }
+}
+
+impl Debug for PostProcessFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PostProcessFn::Fn(_) => f.write_str("PostProcessFn::Fn"),
+            PostProcessFn::None => f.write_str("PostProcessFn::None"),
+        }
+    }
+}
+
 /// A single tiled image
 pub trait TileProvider: Debug {
     /// Provide a list of image tiles. Should be called repetitively until it returns
@@ -148,6 +188,16 @@ pub trait TileProvider: Debug {
         None
     }
 
+    /// The size of the tiles, when it is known before downloading them
+    fn tile_size_hint(&self) -> Option<Vec2d> {
+        None
+    }
+
+    /// The number of tiles, when it is known before downloading them
+    fn tile_count_hint(&self) -> Option<u64> {
+        None
+    }
+
     /// A collection of http headers to use when requesting the tiles
     fn http_headers(&self) -> HashMap<String, String> {
         HashMap::new()
@@ -258,6 +308,14 @@ impl<T: TilesRect> TileProvider for T {
         Some(self.size())
     }
 
+    fn tile_size_hint(&self) -> Option<Vec2d> {
+        Some(TilesRect::tile_size(self))
+    }
+
+    fn tile_count_hint(&self) -> Option<u64> {
+        Some(self.tile_count().into())
+    }
+
     fn http_headers(&self) -> HashMap<String, String> {
         let mut headers = HashMap::new();
         // By default, use the first tile as the referer, so that it is on the same domain
@@ -276,7 +334,7 @@ impl FromStr for TileReference {
     type Err = ZoomError;
 
     fn from_str(tile_str: &str) -> Result<Self, Self::Err> {
-        let mut parts = tile_str.split(' ');
+        let mut parts = tile_str.splitn(3, ' ');
         let make_error = || ZoomError::MalformedTileStr {
             tile_str: String::from(tile_str),
         };
@@ -356,4 +414,18 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn stateful_post_process() {
+        let key = 0x2A;
+        let xor = PostProcessFn::new(move |_tile, bytes| {
+            Ok(bytes.into_iter().map(|b| b ^ key).collect())
+        });
+        let tile = TileReference {
+            url: "0,0".into(),
+            position: Vec2d { x: 0, y: 0 },
+        };
+        assert_eq!(xor.apply(&tile, vec![0x2A, 0]).unwrap(), vec![0, 0x2A]);
+        assert_eq!(PostProcessFn::None.apply(&tile, vec![1]).unwrap(), vec![1]);
+    }
 }
@@ -1,16 +1,14 @@
 use image::{
-    ColorType, GenericImageView, ImageBuffer, ImageResult, Pixel, PixelWithColorType, Rgb, Rgba,
+    DynamicImage, GenericImageView, ImageBuffer, ImageResult, Pixel, PixelWithColorType, Rgb, Rgba,
 };
 use log::debug;
 use std::io;
 use std::path::{Path, PathBuf};
 
-use crate::encoder::Encoder;
+use crate::encoder::{Encoder, MemoryImage};
 use crate::tile::Tile;
 use crate::Vec2d;
 use crate::ZoomError;
-use std::fs::File;
-use std::io::BufWriter;
 
 type CanvasBuffer<Pix> = ImageBuffer<Pix, Vec<<Pix as Pixel>::Subpixel>>;
 
@@ -29,15 +27,11 @@ impl<Pix: Pixel> Canvas<Pix> {
         })
     }
 
-    pub fn new_jpeg(
-        destination: PathBuf,
-        size: Vec2d,
-        quality: u8,
-    ) -> Result<Canvas<Rgb<u8>>, ZoomError> {
-        Ok(Canvas::<Rgb<u8>> {
+    pub fn new_memory(memory: MemoryImage, size: Vec2d) -> Result<Canvas<Rgba<u8>>, ZoomError> {
+        Ok(Canvas::<Rgba<u8>> {
             image: ImageBuffer::new(size.x, size.y),
-            destination,
-            image_writer: ImageWriter::Jpeg { quality },
+            destination: PathBuf::new(),
+            image_writer: ImageWriter::Memory(memory),
         })
     }
 }
@@ -60,6 +54,8 @@ impl FromRgba for Rgb<u8> {
 
 impl<Pix: Pixel<Subpixel = u8> + PixelWithColorType + Send + FromRgba + 'static> Encoder
     for Canvas<Pix>
+where
+    CanvasBuffer<Pix>: Into<DynamicImage>,
 {
     fn add_tile(&mut self, tile: Tile) -> io::Result<()> {
         debug!("Copying tile data from {:?}", tile);
@@ -85,11 +81,16 @@ impl<Pix: Pixel<Subpixel = u8> + PixelWithColorType + Send + FromRgba + 'static>
     }
 
     fn finalize(&mut self) -> io::Result<()> {
+        if let ImageWriter::Memory(memory) = &self.image_writer {
+            let image = std::mem::replace(&mut self.image, ImageBuffer::new(0, 0));
+            memory.set(image.into());
+            return Ok(());
+        }
         self.image_writer
             .write(&self.image, &self.destination)
             .map_err(|e| match e {
                 image::ImageError::IoError(e) => e,
-                other => io::Error::new(io::ErrorKind::Other, other),
+                other => io::Error::other(other),
             })?;
         Ok(())
     }
@@ -101,7 +102,7 @@ impl<Pix: Pixel<Subpixel = u8> + PixelWithColorType + Send + FromRgba + 'static>
 
 pub enum ImageWriter {
     Generic,
-    Jpeg { quality: u8 },
+    Memory(MemoryImage),
 }
 
 impl ImageWriter {
@@ -110,21 +111,11 @@ impl ImageWriter {
         image: &CanvasBuffer<Pix>,
         destination: &Path,
     ) -> ImageResult<()> {
-        match *self {
-            ImageWriter::Jpeg { quality } => {
-                let file = File::create(destination)?;
-                let fout = &mut BufWriter::new(file);
-                let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(fout, quality);
-                encoder.encode(
-                    image.as_raw(),
-                    image.width(),
-                    image.height(),
-                    ColorType::Rgb8,
-                )?;
-            }
+        match self {
             ImageWriter::Generic => {
                 image.save(destination)?;
             }
+            ImageWriter::Memory(_) => unreachable!("Images in memory are not written to a file"),
         };
         Ok(())
     }
@@ -0,0 +1,201 @@
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use log::debug;
+
+use crate::encoder::retiler::{Retiler, TileSaver};
+use crate::tile::Tile;
+use crate::{Vec2d, ZoomError};
+
+use super::{write_tile, Encoder, TileFormat};
+
+pub const DEFAULT_DZI_TILE_SIZE: u32 = 254;
+
+/// Writes a deep zoom image: an xml file, and a `<name>_files` folder containing a folder of tiles
+/// for each zoom level, from a single pixel to the full image
+pub struct DziEncoder {
+    retiler: Retiler<DziTileSaver>,
+    destination: PathBuf,
+    tile_size: u32,
+    overlap: u32,
+    format: TileFormat,
+}
+
+impl DziEncoder {
+    pub fn new(
+        destination: PathBuf,
+        size: Vec2d,
+        tile_size: u32,
+        overlap: u32,
+        format: TileFormat,
+        quality: u8,
+    ) -> Result<Self, ZoomError> {
+        if overlap >= tile_size {
+            return Err(ZoomError::TileOverlapTooLarge { overlap, tile_size });
+        }
+        let stem = destination
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let files_path = destination.with_file_name(format!("{}_files", stem));
+        debug!(
+            "Creating the deep zoom tiles directory at {:?}",
+            &files_path
+        );
+        std::fs::create_dir_all(&files_path)?;
+        let tile_saver = DziTileSaver {
+            files_path,
+            max_level: max_level(size),
+            tile_size,
+            overlap,
+            format,
+            quality,
+        };
+        let retiler = Retiler::full_pyramid(
+            size,
+            Vec2d::square(tile_size),
+            overlap,
+            Arc::new(tile_saver),
+        );
+        debug_assert_eq!(retiler.level_count(), max_level(size) + 1);
+        Ok(DziEncoder {
+            retiler,
+            destination,
+            tile_size,
+            overlap,
+            format,
+        })
+    }
+}
+
+/// The index of the level that contains the full image. Level 0 is a single pixel.
+fn max_level(size: Vec2d) -> u32 {
+    let largest = size.x.max(size.y).max(1);
+    u32::BITS - (largest - 1).leading_zeros()
+}
+
+impl Encoder for DziEncoder {
+    fn add_tile(&mut self, tile: Tile) -> io::Result<()> {
+        self.retiler.add_tile(&tile)
+    }
+
+    fn finalize(&mut self) -> io::Result<()> {
+        self.retiler.finalize();
+        let Vec2d { x, y } = self.size();
+        let dzi = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<Image xmlns="http://schemas.microsoft.com/deepzoom/2008" Format="{}" Overlap="{}" TileSize="{}">
+  <Size Width="{}" Height="{}"/>
+</Image>
+"#,
+            self.format.extension(),
+            self.overlap,
+            self.tile_size,
+            x,
+            y
+        );
+        debug!("Writing the deep zoom metadata to {:?}", &self.destination);
+        std::fs::write(&self.destination, dzi)
+    }
+
+    fn size(&self) -> Vec2d {
+        self.retiler.size()
+    }
+}
+
+struct DziTileSaver {
+    files_path: PathBuf,
+    max_level: u32,
+    tile_size: u32,
+    overlap: u32,
+    format: TileFormat,
+    quality: u8,
+}
+
+impl TileSaver for DziTileSaver {
+    fn save_tile(&self, _size: Vec2d, scale_factor: u32, tile: Tile) -> io::Result<()> {
+        let level = self.max_level - scale_factor.trailing_zeros();
+        // Tiles start `overlap` pixels before their position in the grid, except on the first row and column
+        let Vec2d { x, y } = (tile.position / scale_factor + Vec2d::square(self.overlap))
+            / Vec2d::square(self.tile_size);
+        let level_path = self.files_path.join(level.to_string());
+        std::fs::create_dir_all(&level_path)?;
+        let image_path = level_path.join(format!("{}_{}.{}", x, y, self.format.extension()));
+        write_tile(&tile.image, &image_path, self.format, self.quality)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgb;
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::encoder::test_utils::encode_gradient;
+
+    #[test]
+    fn test_max_level() {
+        assert_eq!(max_level(Vec2d { x: 1, y: 1 }), 0);
+        assert_eq!(max_level(Vec2d { x: 512, y: 3 }), 9);
+        assert_eq!(max_level(Vec2d { x: 513, y: 3 }), 10);
+    }
+
+    #[test]
+    fn test_dzi_pyramid() {
+        let size = Vec2d { x: 40, y: 20 };
+        let (dir, destination) = encode_gradient("image.dzi", size, |path| {
+            DziEncoder::new(path, size, 16, 1, TileFormat::Jpg, 90)
+        });
+
+        let dzi = std::fs::read_to_string(&destination).unwrap();
+        assert!(dzi.contains(r#"Overlap="1" TileSize="16""#), "{}", dzi);
+        assert!(dzi.contains(r#"<Size Width="40" Height="20"/>"#), "{}", dzi);
+
+        let files = dir.path().join("image_files");
+        let dimensions = |path: &str| image::image_dimensions(files.join(path)).unwrap();
+        // Tiles overlap their neighbors by one pixel on each side
+        assert_eq!(dimensions("6/0_0.jpg"), (17, 17));
+        assert_eq!(dimensions("6/1_0.jpg"), (18, 17));
+        assert_eq!(dimensions("6/2_0.jpg"), (9, 17));
+        assert_eq!(dimensions("6/2_1.jpg"), (9, 5));
+        assert_eq!(dimensions("5/1_0.jpg"), (5, 10));
+        assert_eq!(dimensions("0/0_0.jpg"), (1, 1));
+        assert!(!files.join("7").exists());
+        // The first column of the second tile is the last column of the first one
+        let second_tile = image::open(files.join("6/1_0.jpg")).unwrap().to_rgb8();
+        assert!(second_tile.get_pixel(0, 8)[0].abs_diff(15) < 4);
+    }
+
+    #[test]
+    fn test_dzi_overlap_too_large() {
+        let dir = TempDir::new("dezoomify-dzi").unwrap();
+        let destination = dir.path().join("image.dzi");
+        let size = Vec2d { x: 40, y: 20 };
+        let result = DziEncoder::new(destination, size, 254, 300, TileFormat::Jpg, 90);
+        assert!(matches!(
+            result,
+            Err(ZoomError::TileOverlapTooLarge {
+                overlap: 300,
+                tile_size: 254
+            })
+        ));
+    }
+
+    #[test]
+    fn test_dzi_png_tiles() {
+        let size = Vec2d { x: 20, y: 10 };
+        let (dir, destination) = encode_gradient("image.dzi", size, |path| {
+            DziEncoder::new(path, size, 16, 0, TileFormat::Png, 90)
+        });
+
+        let dzi = std::fs::read_to_string(&destination).unwrap();
+        assert!(dzi.contains(r#"Format="png""#), "{}", dzi);
+        // Png tiles are lossless
+        let tile = image::open(dir.path().join("image_files/5/1_0.png"))
+            .unwrap()
+            .to_rgb8();
+        assert_eq!(tile.dimensions(), (4, 10));
+        assert_eq!(tile.get_pixel(3, 9), &Rgb([19, 9, 100]));
+    }
+}
@@ -1,41 +1,143 @@
-use std::fs::File;
 use std::fs::OpenOptions;
 use std::io;
-use std::io::{BufWriter, Write};
+use std::io::Write;
 use std::path::PathBuf;
 use std::sync::Arc;
 
-use image::codecs::jpeg::JpegEncoder;
+use image::DynamicImage;
 use log::debug;
+use serde_json::json;
 
 use crate::encoder::retiler::{Retiler, TileSaver};
-use crate::errors::image_error_to_io_error;
-use crate::iiif::tile_info;
 use crate::tile::Tile;
 use crate::{Vec2d, ZoomError};
 
-use super::Encoder;
+use super::{write_tile, Encoder, EncoderOptions, IiifVersion, TileFormat};
 
+pub const DEFAULT_IIIF_TILE_SIZE: u32 = 512;
+
+/// Writes a level 0 IIIF image: a folder of pre-rendered tiles with an `info.json` file,
+/// and a viewer page
 pub struct IiifEncoder {
     retiler: Retiler<IIIFTileSaver>,
     root_path: PathBuf,
+    id: String,
+    version: IiifVersion,
+    format: TileFormat,
+    list_sizes: bool,
 }
 
 impl IiifEncoder {
-    pub fn new(destination: PathBuf, size: Vec2d, quality: u8) -> Result<Self, ZoomError> {
+    pub fn new(
+        destination: PathBuf,
+        size: Vec2d,
+        quality: u8,
+        options: &EncoderOptions,
+    ) -> Result<Self, ZoomError> {
         let _ = std::fs::remove_file(&destination);
         debug!("Creating IIIF  directory at {:?}", &destination);
-        std::fs::create_dir(&destination)?;
-        let tile_saver = IIIFTileSaver {
+        std::fs::create_dir_all(&destination)?;
+        let tile_saver = Arc::new(IIIFTileSaver {
             root_path: destination.clone(),
+            image_size: size,
+            version: options.iiif_version,
+            format: options.tile_format,
             quality,
+        });
+        let tile_size = Vec2d::square(options.tile_size.unwrap_or(DEFAULT_IIIF_TILE_SIZE));
+        let retiler = if options.iiif_sizes {
+            Retiler::full_pyramid(size, tile_size, 0, tile_saver)
+        } else {
+            Retiler::new(size, tile_size, tile_saver, 1)
         };
-        let tile_size = Vec2d::square(512);
+        let id = options
+            .iiif_base_url
+            .as_deref()
+            .map(|url| url.trim_end_matches('/'))
+            .unwrap_or(".")
+            .to_string();
         Ok(IiifEncoder {
-            retiler: Retiler::new(size, tile_size, Arc::new(tile_saver), 1),
+            retiler,
             root_path: destination,
+            id,
+            version: options.iiif_version,
+            format: options.tile_format,
+            list_sizes: options.iiif_sizes,
         })
     }
+
+    fn scale_factors(&self) -> Vec<u32> {
+        (0..self.retiler.level_count())
+            .map(|n| 2u32.pow(n))
+            .collect()
+    }
+
+    /// The sizes of the levels that fit in a single tile, from the smallest.
+    /// The whole image is saved at these sizes.
+    fn sizes(&self) -> Vec<Vec2d> {
+        let tile_size = self.retiler.tile_size;
+        self.scale_factors()
+            .into_iter()
+            .rev()
+            .map(|scale_factor| self.size().ceil_div(scale_factor))
+            .filter(|size| size.fits_inside(tile_size))
+            .collect()
+    }
+
+    fn info_json(&self) -> serde_json::Value {
+        let Vec2d {
+            x: width,
+            y: height,
+        } = self.size();
+        let tile_size = self.retiler.tile_size;
+        let tiles = json!([{
+            "width": tile_size.x,
+            "height": tile_size.y,
+            "scaleFactors": self.scale_factors(),
+        }]);
+        let format = self.format.extension();
+        let mut info = match self.version {
+            IiifVersion::V2 => json!({
+                "@context": "http://iiif.io/api/image/2/context.json",
+                "@id": self.id,
+                "protocol": "http://iiif.io/api/image",
+                "profile": [
+                    "http://iiif.io/api/image/2/level0.json",
+                    {
+                        "formats": [format],
+                        "qualities": ["default"],
+                        "supports": ["sizeByW"],
+                    },
+                ],
+                "width": width,
+                "height": height,
+                "tiles": tiles,
+            }),
+            IiifVersion::V3 => json!({
+                "@context": "http://iiif.io/api/image/3/context.json",
+                "id": self.id,
+                "type": "ImageService3",
+                "protocol": "http://iiif.io/api/image",
+                "profile": "level0",
+                "width": width,
+                "height": height,
+                "tiles": tiles,
+                "preferredFormats": [format],
+            }),
+        };
+        if self.version == IiifVersion::V3 && self.format != TileFormat::Jpg {
+            info["extraFormats"] = json!([format]);
+        }
+        if self.list_sizes {
+            let sizes: Vec<_> = self
+                .sizes()
+                .into_iter()
+                .map(|s| json!({ "width": s.x, "height": s.y }))
+                .collect();
+            info["sizes"] = json!(sizes);
+        }
+        info
+    }
 }
 
 impl Encoder for IiifEncoder {
@@ -45,28 +147,7 @@ impl Encoder for IiifEncoder {
 
     fn finalize(&mut self) -> io::Result<()> {
         self.retiler.finalize();
-        let scale_factors = (0..self.retiler.level_count())
-            .map(|n| 2u32.pow(n))
-            .collect::<Vec<_>>();
-        let tile_size = self.retiler.tile_size;
-        let image_info = tile_info::ImageInfo {
-            context: Some("http://iiif.io/api/image/3/context.json".to_string()),
-            iiif_type: Some("ImageService3".to_string()),
-            protocol: Some("http://iiif.io/api/image".to_string()),
-            profile: Some(tile_info::Profile::Reference("level0".to_string())),
-            id: Some(".".to_string()),
-            width: self.size().x,
-            height: self.size().y,
-            qualities: Some(vec!["default".into()]),
-            formats: Some(vec!["jpg".into()]),
-            tiles: Some(vec![tile_info::TileInfo {
-                width: tile_size.x,
-                height: Some(tile_size.y),
-                scale_factors,
-            }]),
-            ..Default::default()
-        };
-        let info_json_str = serde_json::to_string(&image_info)?;
+        let info_json_str = serde_json::to_string(&self.info_json())?;
         let info_json_path = self.root_path.join("info.json");
         let viewer_path = self.root_path.join("viewer.html");
         debug!("Writing iiif metadata to {:?}", info_json_path);
@@ -100,30 +181,154 @@ impl Encoder for IiifEncoder {
 
 struct IIIFTileSaver {
     root_path: PathBuf,
+    image_size: Vec2d,
+    version: IiifVersion,
+    format: TileFormat,
     quality: u8,
 }
 
+impl IIIFTileSaver {
+    /// The size part of the canonical url of an image of the given size
+    fn size_str(&self, size: Vec2d) -> String {
+        match self.version {
+            IiifVersion::V2 => format!("{},", size.x),
+            IiifVersion::V3 => format!("{},{}", size.x, size.y),
+        }
+    }
+
+    fn write(&self, image: &DynamicImage, region: &str, size: &str) -> io::Result<()> {
+        let rotation = "0";
+        let image_dir_path = self.root_path.join(region).join(size).join(rotation);
+        std::fs::create_dir_all(&image_dir_path)?;
+        let filename = format!("default.{}", self.format.extension());
+        write_tile(
+            image,
+            &image_dir_path.join(filename),
+            self.format,
+            self.quality,
+        )
+    }
+}
+
 impl TileSaver for IIIFTileSaver {
-    fn save_tile(&self, size: Vec2d, tile: Tile) -> io::Result<()> {
+    fn save_tile(&self, size: Vec2d, scale_factor: u32, tile: Tile) -> io::Result<()> {
         let tile_size = tile.size();
         let region = format!(
             "{},{},{},{}",
             tile.position.x, tile.position.y, size.x, size.y
         );
-        let tile_size_str = format!("{},{}", tile_size.x, tile_size.y);
-        let rotation = "0";
-        let filename = "default.jpg";
-        let mut image_dir_path = self.root_path.clone();
-        image_dir_path.push(region);
-        image_dir_path.push(tile_size_str);
-        image_dir_path.push(rotation);
-        let image_path = image_dir_path.join(filename);
-        debug!("Writing tile to {:?}", image_path);
-        std::fs::create_dir_all(&image_dir_path)?;
-        let file = &mut BufWriter::new(File::create(&image_path)?);
-        let jpeg_writer = JpegEncoder::new_with_quality(file, self.quality);
-        tile.image
-            .write_with_encoder(jpeg_writer)
-            .map_err(image_error_to_io_error)
+        self.write(&tile.image, &region, &self.size_str(tile_size))?;
+        // Viewers request the levels that fit in a single tile with the "full" region
+        if tile.position == Vec2d::default() && size == self.image_size {
+            self.write(&tile.image, "full", &self.size_str(tile_size))?;
+            if scale_factor == 1 {
+                self.write(&tile.image, "full", "max")?;
+                if self.version == IiifVersion::V2 {
+                    self.write(&tile.image, "full", "full")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgb;
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::encoder::test_utils::encode_gradient;
+    use crate::iiif::tile_info::{ImageInfo, TileSizeFormat};
+
+    fn encode(size: Vec2d, options: &EncoderOptions) -> (TempDir, PathBuf) {
+        encode_gradient("image.iiif", size, |path| {
+            IiifEncoder::new(path, size, 90, options)
+        })
+    }
+
+    #[test]
+    fn test_iiif_v3() {
+        let options = EncoderOptions {
+            tile_size: Some(16),
+            ..EncoderOptions::default()
+        };
+        let (_dir, root) = encode(Vec2d { x: 40, y: 20 }, &options);
+        let info_str = std::fs::read_to_string(root.join("info.json")).unwrap();
+        let info: serde_json::Value = serde_json::from_str(&info_str).unwrap();
+        assert_eq!(info["id"], ".");
+        assert_eq!(info["type"], "ImageService3");
+        assert_eq!(info["tiles"][0]["scaleFactors"], json!([1, 2, 4]));
+        assert!(info.get("sizes").is_none());
+        let dimensions = |path: &str| image::image_dimensions(root.join(path)).unwrap();
+        assert_eq!(dimensions("32,16,8,4/8,4/0/default.jpg"), (8, 4));
+        assert_eq!(dimensions("32,0,8,20/4,10/0/default.jpg"), (4, 10));
+        assert_eq!(dimensions("full/10,5/0/default.jpg"), (10, 5));
+        // The dezoomer can read the images it writes
+        let image_info: ImageInfo = serde_json::from_str(&info_str).unwrap();
+        assert_eq!(image_info.best_format(), "jpg");
+        assert_eq!(
+            image_info.preferred_size_format(),
+            TileSizeFormat::WidthHeight
+        );
+    }
+
+    #[test]
+    fn test_iiif_v2_png_sizes() {
+        let options = EncoderOptions {
+            tile_size: Some(16),
+            tile_format: TileFormat::Png,
+            iiif_version: IiifVersion::V2,
+            iiif_sizes: true,
+            iiif_base_url: Some("https://example.org/iiif/image/".into()),
+            ..EncoderOptions::default()
+        };
+        let (_dir, root) = encode(Vec2d { x: 40, y: 20 }, &options);
+        let info_str = std::fs::read_to_string(root.join("info.json")).unwrap();
+        let info: serde_json::Value = serde_json::from_str(&info_str).unwrap();
+        assert_eq!(info["@id"], "https://example.org/iiif/image");
+        assert_eq!(info["profile"][1]["formats"], json!(["png"]));
+        assert_eq!(
+            info["tiles"][0]["scaleFactors"],
+            json!([1, 2, 4, 8, 16, 32, 64])
+        );
+        assert_eq!(
+            info["sizes"],
+            json!([
+                {"width": 1, "height": 1},
+                {"width": 2, "height": 1},
+                {"width": 3, "height": 2},
+                {"width": 5, "height": 3},
+                {"width": 10, "height": 5},
+            ])
+        );
+        let tile = image::open(root.join("16,0,16,16/16,/0/default.png"))
+            .unwrap()
+            .to_rgb8();
+        assert_eq!(tile.get_pixel(3, 9), &Rgb([19, 9, 100]));
+        for size in ["1,", "10,"] {
+            assert!(root.join("full").join(size).join("0/default.png").exists());
+        }
+        let image_info: ImageInfo = serde_json::from_str(&info_str).unwrap();
+        assert_eq!(image_info.best_format(), "png");
+        assert_eq!(image_info.preferred_size_format(), TileSizeFormat::Width);
+    }
+
+    #[test]
+    fn test_iiif_whole_image_in_a_tile() {
+        let options = EncoderOptions {
+            tile_format: TileFormat::Webp,
+            ..EncoderOptions::default()
+        };
+        let (_dir, root) = encode(Vec2d { x: 30, y: 20 }, &options);
+        let info: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(root.join("info.json")).unwrap()).unwrap();
+        assert_eq!(info["extraFormats"], json!(["webp"]));
+        assert_eq!(info["tiles"][0]["width"], DEFAULT_IIIF_TILE_SIZE);
+        let image = image::open(root.join("full/max/0/default.webp"))
+            .unwrap()
+            .to_rgb8();
+        // Webp tiles are lossless
+        assert_eq!(image.get_pixel(29, 19), &Rgb([29, 19, 100]));
     }
 }
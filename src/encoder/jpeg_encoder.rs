@@ -0,0 +1,224 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread::JoinHandle;
+
+use ::jpeg_encoder::{rgb_to_ycbcr, EncodingError, ImageBuffer, JpegColorType};
+use log::debug;
+
+use crate::tile::Tile;
+use crate::{Vec2d, ZoomError};
+
+use super::pixel_streamer::PixelStreamer;
+use super::Encoder;
+
+/// The number of rows of pixels that can wait to be encoded
+const ROWS_IN_FLIGHT: usize = 64;
+
+/// Writes a baseline jpeg file from the top to the bottom, as the rows of pixels become available.
+/// Only a few rows of pixels are kept in memory, instead of the whole image.
+/// The rows are compressed in a separate thread.
+pub struct JpegEncoder {
+    pixel_streamer: Option<PixelStreamer<RowSender>>,
+    encoding: Option<JoinHandle<Result<(), EncodingError>>>,
+    size: Vec2d,
+}
+
+impl JpegEncoder {
+    pub fn new(destination: PathBuf, size: Vec2d, quality: u8) -> Result<Self, ZoomError> {
+        let (Ok(width), Ok(height)) = (u16::try_from(size.x), u16::try_from(size.y)) else {
+            return Err(ZoomError::JpegTooLarge {
+                width: size.x,
+                height: size.y,
+            });
+        };
+        let file = File::create(destination)?;
+        let row_len = 3 * size.x as usize;
+        let (sender, receiver) = sync_channel(ROWS_IN_FLIGHT);
+        let rows = ReceivedRows::new(receiver, width, height);
+        let encoding = std::thread::spawn(move || {
+            let mut writer = BufWriter::new(file);
+            let encoder = ::jpeg_encoder::Encoder::new(&mut writer, quality.max(1));
+            encoder.encode_image(rows)?;
+            writer.flush()?;
+            Ok(())
+        });
+        let row_sender = RowSender {
+            sender,
+            row: Vec::with_capacity(row_len),
+            row_len,
+        };
+        Ok(JpegEncoder {
+            pixel_streamer: Some(PixelStreamer::new(row_sender, size)),
+            encoding: Some(encoding),
+            size,
+        })
+    }
+}
+
+impl Encoder for JpegEncoder {
+    fn add_tile(&mut self, tile: Tile) -> io::Result<()> {
+        self.pixel_streamer
+            .as_mut()
+            .expect("tried to add a tile in a finalized image")
+            .add_tile(tile)
+    }
+
+    fn finalize(&mut self) -> io::Result<()> {
+        let mut pixel_streamer = self
+            .pixel_streamer
+            .take()
+            .expect("Tried to finalize an image twice");
+        let streamed = pixel_streamer.finalize();
+        // Closes the channel, so that the encoding thread does not wait for more rows
+        drop(pixel_streamer);
+        debug!("Waiting for the end of the jpeg encoding");
+        let encoding = self
+            .encoding
+            .take()
+            .expect("The encoding thread should exist");
+        encoding
+            .join()
+            .map_err(|_| io::Error::other("The jpeg encoding thread panicked"))?
+            .map_err(|e| match e {
+                EncodingError::IoError(e) => e,
+                other => io::Error::other(other),
+            })?;
+        streamed
+    }
+
+    fn size(&self) -> Vec2d {
+        self.size
+    }
+}
+
+/// Sends the pixels it receives to the encoding thread, one row at a time
+struct RowSender {
+    sender: SyncSender<Vec<u8>>,
+    row: Vec<u8>,
+    row_len: usize,
+}
+
+impl Write for RowSender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.row_len - self.row.len());
+        self.row.extend_from_slice(&buf[..len]);
+        if self.row.len() == self.row_len {
+            let row = std::mem::replace(&mut self.row, Vec::with_capacity(self.row_len));
+            self.sender.send(row).map_err(|_| {
+                io::Error::new(io::ErrorKind::BrokenPipe, "The jpeg encoding stopped")
+            })?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The image given to the jpeg encoder, whose rows arrive from a channel, in order
+struct ReceivedRows {
+    receiver: Receiver<Vec<u8>>,
+    width: u16,
+    height: u16,
+    /// The last row received, with its index
+    current: RefCell<(Option<u16>, Vec<u8>)>,
+}
+
+impl ReceivedRows {
+    fn new(receiver: Receiver<Vec<u8>>, width: u16, height: u16) -> Self {
+        ReceivedRows {
+            receiver,
+            width,
+            height,
+            current: RefCell::new((None, vec![0; 3 * width as usize])),
+        }
+    }
+}
+
+impl ImageBuffer for ReceivedRows {
+    fn get_jpeg_color_type(&self) -> JpegColorType {
+        JpegColorType::Ycbcr
+    }
+
+    fn width(&self) -> u16 {
+        self.width
+    }
+
+    fn height(&self) -> u16 {
+        self.height
+    }
+
+    fn fill_buffers(&self, y: u16, buffers: &mut [Vec<u8>; 4]) {
+        let mut current = self.current.borrow_mut();
+        // The encoder asks for the last row several times to fill the last blocks
+        if current.0 != Some(y) {
+            match self.receiver.recv() {
+                Ok(row) => current.1 = row,
+                // The channel was closed early: the rest of the image is left black
+                Err(_) => current.1.fill(0),
+            }
+            current.0 = Some(y);
+        }
+        for pixel in current.1.chunks_exact(3) {
+            let (y, cb, cr) = rgb_to_ycbcr(pixel[0], pixel[1], pixel[2]);
+            buffers[0].push(y);
+            buffers[1].push(cb);
+            buffers[2].push(cr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::encoder::test_utils::encode_gradient;
+
+    #[test]
+    fn test_jpeg_stream() {
+        let size = Vec2d { x: 40, y: 30 };
+        let (_dir, destination) = encode_gradient("image.jpg", Vec2d { x: 40, y: 20 }, |path| {
+            JpegEncoder::new(path, size, 95)
+        });
+
+        let image = image::open(&destination).unwrap().to_rgb8();
+        assert_eq!(image.dimensions(), (40, 30));
+        let [r, g, b] = image.get_pixel(20, 2).0;
+        assert!(r.abs_diff(20) < 4 && g.abs_diff(2) < 4 && b.abs_diff(100) < 4);
+        // The bottom of the image was never received, so it is black
+        assert!(image.get_pixel(20, 25).0.iter().all(|&c| c < 10));
+    }
+
+    #[test]
+    fn test_jpeg_rows_missing() {
+        let (sender, receiver) = sync_channel(ROWS_IN_FLIGHT);
+        for _ in 0..10 {
+            sender.send(vec![255; 3 * 8]).unwrap();
+        }
+        // The rows stop arriving in the middle of the image
+        drop(sender);
+        let mut jpeg = vec![];
+        ::jpeg_encoder::Encoder::new(&mut jpeg, 95)
+            .encode_image(ReceivedRows::new(receiver, 8, 30))
+            .unwrap();
+
+        let image = image::load_from_memory(&jpeg).unwrap().to_rgb8();
+        assert_eq!(image.dimensions(), (8, 30));
+        assert!(image.get_pixel(4, 5).0.iter().all(|&c| c > 245));
+        // The last row received is not repeated until the bottom
+        assert!(image.get_pixel(4, 25).0.iter().all(|&c| c < 10));
+    }
+
+    #[test]
+    fn test_jpeg_too_large() {
+        let dir = TempDir::new("dezoomify-jpeg").unwrap();
+        let size = Vec2d { x: 70_000, y: 10 };
+        let result = JpegEncoder::new(dir.path().join("image.jpg"), size, 95);
+        assert!(matches!(result, Err(ZoomError::JpegTooLarge { .. })));
+    }
+}
@@ -1,17 +1,31 @@
-use std::path::PathBuf;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
-use image::{DynamicImage, GenericImageView, Rgb, Rgba, SubImage};
+use clap::ValueEnum;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{DynamicImage, GenericImageView, Rgba, SubImage};
 use log::debug;
 
+use crate::errors::image_error_to_io_error;
 use crate::tile::Tile;
 use crate::{max_size_in_rect, Vec2d, ZoomError};
 
 pub mod canvas;
+pub mod dzi_encoder;
 pub mod iiif_encoder;
+pub mod jpeg_encoder;
 pub mod pixel_streamer;
 pub mod png_encoder;
+pub mod raw_encoder;
 mod retiler;
+pub mod tiff_encoder;
 pub mod tile_buffer;
+pub mod zoomify_encoder;
 
 pub trait Encoder: Send + 'static {
     /// Add a tile to the image
@@ -22,11 +36,178 @@ pub trait Encoder: Send + 'static {
     fn size(&self) -> Vec2d;
 }
 
+/// Settings of the image encoders
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncoderOptions {
+    /// See `--compression`
+    pub compression: u8,
+    /// Also write the lower resolutions of the image in tiff files
+    pub tiff_pyramid: bool,
+    /// Size of the tiles of tiled output formats, instead of the default size of the format.
+    /// It must not be zero.
+    pub tile_size: Option<u32>,
+    /// Number of pixels shared by neighboring tiles in deep zoom output
+    pub tile_overlap: u32,
+    /// Image format of the tiles in iiif and deep zoom output
+    pub tile_format: TileFormat,
+    /// Version of the IIIF image API followed by the `info.json` of iiif output
+    pub iiif_version: IiifVersion,
+    /// Also write downscaled versions of the whole image in iiif output, and list them in `sizes`
+    pub iiif_sizes: bool,
+    /// The URL at which the iiif output will be published. By default, the `id` is relative.
+    pub iiif_base_url: Option<String>,
+}
+
+impl Default for EncoderOptions {
+    fn default() -> Self {
+        EncoderOptions {
+            compression: 20,
+            tiff_pyramid: false,
+            tile_size: None,
+            tile_overlap: 1,
+            tile_format: TileFormat::default(),
+            iiif_version: IiifVersion::default(),
+            iiif_sizes: false,
+            iiif_base_url: None,
+        }
+    }
+}
+
+/// Image format of the tiles written by the tiled encoders
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TileFormat {
+    #[default]
+    Jpg,
+    Png,
+    Webp,
+}
+
+impl TileFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            TileFormat::Jpg => "jpg",
+            TileFormat::Png => "png",
+            TileFormat::Webp => "webp",
+        }
+    }
+}
+
+/// Version of the IIIF image API
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IiifVersion {
+    #[value(name = "2")]
+    V2,
+    #[default]
+    #[value(name = "3")]
+    V3,
+}
+
+/// Write a single tile of a tiled output format to a file.
+/// The quality only applies to jpeg, the other formats are lossless.
+fn write_tile(
+    image: &DynamicImage,
+    path: &Path,
+    format: TileFormat,
+    quality: u8,
+) -> std::io::Result<()> {
+    debug!("Writing tile to {:?}", path);
+    let mut file = BufWriter::new(File::create(path)?);
+    match format {
+        TileFormat::Jpg => {
+            image.write_with_encoder(JpegEncoder::new_with_quality(&mut file, quality))
+        }
+        TileFormat::Png => image.write_with_encoder(PngEncoder::new(&mut file)),
+        TileFormat::Webp => image.write_with_encoder(WebPEncoder::new_lossless(&mut file)),
+    }
+    .map_err(image_error_to_io_error)?;
+    file.flush()
+}
+
+/// Where the assembled image is written
+#[derive(Debug, Clone)]
+pub enum Output {
+    /// An image file, whose format is chosen from its extension
+    File(PathBuf),
+    /// An image kept in memory
+    Memory(MemoryImage),
+    /// The raw RGB pixels of the image, streamed from the top left to the bottom right
+    Pixels(PixelWriter),
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Output::File(path) => write!(f, "{}", path.to_string_lossy()),
+            Output::Memory(_) => write!(f, "memory"),
+            Output::Pixels(_) => write!(f, "the pixel writer"),
+        }
+    }
+}
+
+/// Receives the image assembled in memory
+#[derive(Debug, Clone, Default)]
+pub struct MemoryImage(Arc<Mutex<Option<DynamicImage>>>);
+
+impl MemoryImage {
+    /// The image, once it has been assembled
+    pub fn take(&self) -> Option<DynamicImage> {
+        self.0.lock().unwrap().take()
+    }
+
+    pub(crate) fn set(&self, image: DynamicImage) {
+        *self.0.lock().unwrap() = Some(image);
+    }
+}
+
+/// Creates the writer to which the pixels of the image are streamed,
+/// from the final size of the image. Each pixel is written as three bytes: red, green and blue.
+#[derive(Clone)]
+pub struct PixelWriter(Arc<PixelWriterFn>);
+
+type PixelWriterFn = dyn Fn(Vec2d) -> std::io::Result<Box<dyn Write + Send>> + Send + Sync;
+
+impl PixelWriter {
+    pub fn new<F>(create_writer: F) -> Self
+    where
+        F: Fn(Vec2d) -> std::io::Result<Box<dyn Write + Send>> + Send + Sync + 'static,
+    {
+        PixelWriter(Arc::new(create_writer))
+    }
+}
+
+impl fmt::Debug for PixelWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PixelWriter")
+    }
+}
+
+fn encoder_for_output(
+    output: Output,
+    size: Vec2d,
+    options: EncoderOptions,
+) -> Result<Box<dyn Encoder>, ZoomError> {
+    match output {
+        Output::File(destination) => encoder_for_name(destination, size, options),
+        Output::Memory(memory) => {
+            debug!("Assembling the image in memory");
+            Ok(Box::new(canvas::Canvas::<Rgba<u8>>::new_memory(
+                memory, size,
+            )?))
+        }
+        Output::Pixels(pixel_writer) => {
+            debug!("Streaming the pixels of the image");
+            let writer = (pixel_writer.0)(size)?;
+            Ok(Box::new(raw_encoder::RawEncoder::new(writer, size)))
+        }
+    }
+}
+
 fn encoder_for_name(
     destination: PathBuf,
     size: Vec2d,
-    compression: u8,
+    options: EncoderOptions,
 ) -> Result<Box<dyn Encoder>, ZoomError> {
+    let compression = options.compression;
     let extension = destination.extension().unwrap_or_default();
     let quality = 100u8.saturating_sub(compression);
 
@@ -43,10 +224,44 @@ fn encoder_for_name(
             destination,
             size,
             quality,
+            &options,
+        )?))
+    } else if extension == "dzi" {
+        debug!("Using the deep zoom tiling encoder");
+        Ok(Box::new(dzi_encoder::DziEncoder::new(
+            destination,
+            size,
+            options
+                .tile_size
+                .unwrap_or(dzi_encoder::DEFAULT_DZI_TILE_SIZE),
+            options.tile_overlap,
+            options.tile_format,
+            quality,
+        )?))
+    } else if extension == "zoomify" {
+        debug!("Using the zoomify tiling encoder");
+        Ok(Box::new(zoomify_encoder::ZoomifyEncoder::new(
+            destination,
+            size,
+            options
+                .tile_size
+                .unwrap_or(zoomify_encoder::DEFAULT_ZOOMIFY_TILE_SIZE),
+            quality,
+        )?))
+    } else if extension == "tif" || extension == "tiff" {
+        debug!("Using the streaming tiff encoder");
+        Ok(Box::new(tiff_encoder::TiffEncoder::new(
+            destination,
+            size,
+            compression,
+            options.tiff_pyramid,
         )?))
     } else if extension == "jpeg" || extension == "jpg" {
-        debug!("Using the jpeg encoder with a quality of {}", quality);
-        Ok(Box::new(canvas::Canvas::<Rgb<u8>>::new_jpeg(
+        debug!(
+            "Using the streaming jpeg encoder with a quality of {}",
+            quality
+        );
+        Ok(Box::new(self::jpeg_encoder::JpegEncoder::new(
             destination,
             size,
             quality,
@@ -68,3 +283,48 @@ pub fn crop_tile(tile: &Tile, canvas_size: Vec2d) -> SubImage<&DynamicImage> {
     let Vec2d { x: xmax, y: ymax } = max_size_in_rect(tile.position, tile.size(), canvas_size);
     tile.image.view(0, 0, xmax, ymax)
 }
+
+/// Helpers for the tests of the encoders
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use std::path::PathBuf;
+
+    use image::{DynamicImage, ImageBuffer, Rgb};
+    use tempdir::TempDir;
+
+    use super::Encoder;
+    use crate::tile::Tile;
+    use crate::{Vec2d, ZoomError};
+
+    /// An image in which the red and green values of each pixel are its coordinates
+    pub fn gradient(size: Vec2d) -> DynamicImage {
+        DynamicImage::ImageRgb8(ImageBuffer::from_fn(size.x, size.y, |x, y| {
+            Rgb([x as u8, y as u8, 100])
+        }))
+    }
+
+    /// Create an encoder for the given file name in a temporary folder,
+    /// give it a gradient tile of the given size at the top left of the image, and finalize it.
+    /// Returns the folder, which is removed when it is dropped, and the path of the file.
+    pub fn encode_gradient<E, F>(
+        file_name: &str,
+        tile_size: Vec2d,
+        new_encoder: F,
+    ) -> (TempDir, PathBuf)
+    where
+        E: Encoder,
+        F: FnOnce(PathBuf) -> Result<E, ZoomError>,
+    {
+        let dir = TempDir::new("dezoomify-encoder").unwrap();
+        let destination = dir.path().join(file_name);
+        let mut encoder = new_encoder(destination.clone()).unwrap();
+        encoder
+            .add_tile(Tile {
+                position: Vec2d { x: 0, y: 0 },
+                image: gradient(tile_size),
+            })
+            .unwrap();
+        encoder.finalize().unwrap();
+        (dir, destination)
+    }
+}
@@ -0,0 +1,75 @@
+use std::io::{self, Write};
+
+use crate::tile::Tile;
+use crate::Vec2d;
+
+use super::pixel_streamer::PixelStreamer;
+use super::Encoder;
+
+/// Writes the raw RGB pixels of the image, without any image format
+pub struct RawEncoder {
+    pixel_streamer: PixelStreamer<Box<dyn Write + Send>>,
+    size: Vec2d,
+}
+
+impl RawEncoder {
+    pub fn new(writer: Box<dyn Write + Send>, size: Vec2d) -> Self {
+        RawEncoder {
+            pixel_streamer: PixelStreamer::new(writer, size),
+            size,
+        }
+    }
+}
+
+impl Encoder for RawEncoder {
+    fn add_tile(&mut self, tile: Tile) -> io::Result<()> {
+        self.pixel_streamer.add_tile(tile)
+    }
+
+    fn finalize(&mut self) -> io::Result<()> {
+        self.pixel_streamer.finalize()
+    }
+
+    fn size(&self) -> Vec2d {
+        self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use image::{DynamicImage, ImageBuffer};
+
+    use super::*;
+
+    /// A writer whose contents can be read after it is given to the encoder
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_raw_pixels() {
+        let buffer = SharedBuffer::default();
+        let mut encoder = RawEncoder::new(Box::new(buffer.clone()), Vec2d { x: 2, y: 2 });
+        encoder
+            .add_tile(Tile {
+                position: Vec2d { x: 1, y: 0 },
+                image: DynamicImage::ImageRgb8(ImageBuffer::from_raw(1, 1, vec![1, 2, 3]).unwrap()),
+            })
+            .unwrap();
+        encoder.finalize().unwrap();
+        let mut expected = vec![0; 12];
+        expected[3..6].copy_from_slice(&[1, 2, 3]);
+        assert_eq!(*buffer.0.lock().unwrap(), expected);
+    }
+}
@@ -2,6 +2,7 @@ use std::collections::HashMap;
 use std::convert::TryInto;
 use std::io;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use fixedbitset::FixedBitSet;
@@ -11,11 +12,14 @@ use image::{DynamicImage, GenericImageView, SubImage};
 use log::{debug, warn};
 
 use crate::errors::image_error_to_io_error;
+use crate::Tile;
 use crate::Vec2d;
-use crate::{max_size_in_rect, Tile};
 
 pub trait TileSaver {
-    fn save_tile(&self, size: Vec2d, tile: Tile) -> io::Result<()>;
+    /// Save a tile of the image reduced by the given scale factor.
+    /// `size` is the size of the area of the original image covered by the tile,
+    /// and the position of the tile is given in the original image.
+    fn save_tile(&self, size: Vec2d, scale_factor: u32, tile: Tile) -> io::Result<()>;
 }
 
 /**
@@ -38,34 +42,84 @@ Every level passes the source tile to it's child when it is done with it.
 pub struct Retiler<T: TileSaver> {
     original_size: Vec2d,
     pub tile_size: Vec2d,
+    /// Number of pixels of the original image shared by neighboring tiles, on each side
+    overlap: u32,
     scale_factor: u32,
     next_level: Option<Box<Retiler<T>>>,
     /// This hash map contains target tiles that are being written.
     /// When a target tile has been entirely covered by source tiles, its entry is set to None
     tiles: HashMap<Vec2d, Option<TmpTile>>,
     tile_saver: Arc<T>,
+    /// Distinguishes the temporary tiles of retilers that run at the same time
+    id: usize,
 }
 
+static NEXT_RETILER_ID: AtomicUsize = AtomicUsize::new(0);
+
 struct TmpTile {
     done_pixels: FixedBitSet,
 }
 
 impl<T: TileSaver> Retiler<T> {
     pub fn new(size: Vec2d, tile_size: Vec2d, tile_saver: Arc<T>, scale_factor: u32) -> Retiler<T> {
-        let next_level = if (size / scale_factor).fits_inside(tile_size) {
+        Retiler::build(size, tile_size, 0, tile_saver, scale_factor, false)
+    }
+
+    /// A retiler whose smallest level is a single pixel, as in deep zoom images.
+    /// Neighboring tiles share `overlap` pixels on each side.
+    pub fn full_pyramid(
+        size: Vec2d,
+        tile_size: Vec2d,
+        overlap: u32,
+        tile_saver: Arc<T>,
+    ) -> Retiler<T> {
+        Retiler::build(size, tile_size, overlap, tile_saver, 1, true)
+    }
+
+    fn build(
+        size: Vec2d,
+        tile_size: Vec2d,
+        overlap: u32,
+        tile_saver: Arc<T>,
+        scale_factor: u32,
+        full_pyramid: bool,
+    ) -> Retiler<T> {
+        let is_last_level = if full_pyramid {
+            scale_factor >= size.x.max(size.y)
+        } else {
+            (size / scale_factor).fits_inside(tile_size)
+        };
+        let next_level = if is_last_level {
             None
         } else {
             let tile_saver = Arc::clone(&tile_saver);
-            let level = Retiler::new(size, tile_size, tile_saver, scale_factor * 2);
+            let level = Retiler::build(
+                size,
+                tile_size,
+                overlap,
+                tile_saver,
+                scale_factor * 2,
+                full_pyramid,
+            );
             Some(Box::new(level))
         };
         Retiler {
             original_size: size,
             tile_size: tile_size * scale_factor,
+            overlap: overlap * scale_factor,
             next_level,
             tiles: HashMap::new(),
             tile_saver,
             scale_factor,
+            id: NEXT_RETILER_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// A retiler that does not create the lower resolution levels
+    pub fn single_level(size: Vec2d, tile_size: Vec2d, tile_saver: Arc<T>) -> Retiler<T> {
+        Retiler {
+            next_level: None,
+            ..Retiler::new(size, tile_size, tile_saver, 1)
         }
     }
 
@@ -73,9 +127,13 @@ impl<T: TileSaver> Retiler<T> {
         self.original_size / self.scale_factor
     }
 
+    /// The positions in the tile grid of the tiles that contain pixels of the given area
     fn tile_positions(&self, position: Vec2d, size: Vec2d) -> impl Iterator<Item = Vec2d> {
-        let top_left = (position / self.tile_size) * self.tile_size;
-        let bottom_right = ((position + size).ceil_div(self.tile_size)) * self.tile_size;
+        let overlap = Vec2d::square(self.overlap);
+        let top_left = ((position - overlap) / self.tile_size) * self.tile_size;
+        let bottom_right = ((position + size + overlap).ceil_div(self.tile_size)) * self.tile_size;
+        let bottom_right =
+            bottom_right.min(self.original_size.ceil_div(self.tile_size) * self.tile_size);
         let dy = self.tile_size.y as usize;
         let dx = self.tile_size.x as usize;
         (top_left.y..bottom_right.y).step_by(dy).flat_map(move |y| {
@@ -86,7 +144,6 @@ impl<T: TileSaver> Retiler<T> {
     }
 
     pub fn add_tile(&mut self, tile: &Tile) -> io::Result<()> {
-        let tile_size = self.tile_size;
         let scale_factor = self.scale_factor;
         let scaled_top_left = tile.position() / scale_factor;
         let scaled_bottom_right = tile.bottom_right().ceil_div(scale_factor);
@@ -103,11 +160,11 @@ impl<T: TileSaver> Retiler<T> {
             })
         };
         let scaled_tile = scaled_tile.as_ref().unwrap_or(tile);
-        for cur_pos in covered_tiles_positions {
-            let cur_tile_size = max_size_in_rect(cur_pos, tile_size, self.original_size);
+        for grid_pos in covered_tiles_positions {
+            let (cur_pos, cur_tile_size) = self.tile_area(grid_pos);
             let scaled_tile_size = cur_tile_size.ceil_div(scale_factor);
 
-            let tmp_tile_entry = self.tiles.entry(cur_pos).or_insert_with(|| {
+            let tmp_tile_entry = self.tiles.entry(grid_pos).or_insert_with(|| {
                 debug!(
                     "Creating a new partial tile at scale factor {} position {} size {}",
                     scale_factor, cur_pos, cur_tile_size
@@ -116,6 +173,7 @@ impl<T: TileSaver> Retiler<T> {
             });
             if let Some(tmp_tile) = tmp_tile_entry {
                 let finished = tmp_tile.add_tile(
+                    self.id,
                     cur_pos,
                     cur_tile_size,
                     self.original_size,
@@ -124,7 +182,7 @@ impl<T: TileSaver> Retiler<T> {
                 )?;
                 if let Some(tile_img) = finished {
                     self.tile_save(cur_pos, cur_tile_size, tile_img)?;
-                    self.tiles.insert(cur_pos, None);
+                    self.tiles.insert(grid_pos, None);
                 }
             } else {
                 debug!("Source tiles overlap:\
@@ -142,9 +200,9 @@ impl<T: TileSaver> Retiler<T> {
 
     /// Add all partially downloaded tiles to the final image
     pub fn finalize(&mut self) {
-        for (position, tile_opt) in std::mem::take(&mut self.tiles).into_iter() {
+        for (grid_pos, tile_opt) in std::mem::take(&mut self.tiles).into_iter() {
             if let Some(tile) = tile_opt {
-                let cur_tile_size = max_size_in_rect(position, self.tile_size, self.original_size);
+                let (position, cur_tile_size) = self.tile_area(grid_pos);
                 warn!(
                     "The target tile of size {} at zoom level {} and position {} \
             was not fully covered by source tiles. It misses {} pixels.",
@@ -153,7 +211,7 @@ impl<T: TileSaver> Retiler<T> {
                     position,
                     tile.missing_pixels()
                 );
-                let tmp_tile_path = TmpTile::path(position, self.scale_factor);
+                let tmp_tile_path = TmpTile::path(self.id, position, self.scale_factor);
                 let result = image::open(&tmp_tile_path)
                     .map_err(image_error_to_io_error)
                     .and_then(|image| self.tile_save(position, cur_tile_size, image))
@@ -172,8 +230,18 @@ impl<T: TileSaver> Retiler<T> {
         }
     }
 
+    /// The area of the original image covered by the tile at the given position in the tile grid,
+    /// including the pixels it shares with its neighbors
+    fn tile_area(&self, grid_pos: Vec2d) -> (Vec2d, Vec2d) {
+        let overlap = Vec2d::square(self.overlap);
+        let top_left = grid_pos - overlap;
+        let bottom_right = (grid_pos + self.tile_size + overlap).min(self.original_size);
+        (top_left, bottom_right - top_left)
+    }
+
     pub fn tile_save(&self, position: Vec2d, size: Vec2d, image: DynamicImage) -> io::Result<()> {
-        self.tile_saver.save_tile(size, Tile { image, position })
+        self.tile_saver
+            .save_tile(size, self.scale_factor, Tile { image, position })
     }
 
     pub fn level_count(&self) -> u32 {
@@ -207,6 +275,7 @@ impl TmpTile {
 
     fn add_tile(
         &mut self,
+        retiler_id: usize,
         self_position: Vec2d,
         self_size: Vec2d,
         level_size: Vec2d,
@@ -222,7 +291,7 @@ impl TmpTile {
         let bottom_right = tile.bottom_right().min(self_bottom_right) - scaled_self_position;
         let scaled_size = self_size.ceil_div(scale_factor);
 
-        let tmp_tile_path = Self::path(self_position, scale_factor);
+        let tmp_tile_path = Self::path(retiler_id, self_position, scale_factor);
         debug!(
             "Opening partial tile of size {} at {:?} in order to paste pixels from {} to {}",
             scaled_size, &tmp_tile_path, top_left, bottom_right
@@ -258,12 +327,12 @@ impl TmpTile {
         }
     }
 
-    fn path(position: Vec2d, scale_factor: u32) -> PathBuf {
+    fn path(retiler_id: usize, position: Vec2d, scale_factor: u32) -> PathBuf {
         let pid = std::process::id();
         let mut path = std::env::temp_dir();
         path.push(format!(
-            "dezoomify_{}_level_{}_position_{}x{}.bmp",
-            pid, scale_factor, position.x, position.y
+            "dezoomify_{}_{}_level_{}_position_{}x{}.bmp",
+            pid, retiler_id, scale_factor, position.x, position.y
         ));
         path
     }
@@ -308,7 +377,7 @@ mod tests {
     }
 
     impl TileSaver for TestTileSaver {
-        fn save_tile(&self, size: Vec2d, tile: Tile) -> io::Result<()> {
+        fn save_tile(&self, size: Vec2d, _scale_factor: u32, tile: Tile) -> io::Result<()> {
             self.added.lock().unwrap().push((size, tile));
             Ok(())
         }
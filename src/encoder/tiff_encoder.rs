@@ -0,0 +1,316 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use image::{DynamicImage, GenericImage, RgbImage};
+use log::debug;
+
+use crate::encoder::retiler::{Retiler, TileSaver};
+use crate::tile::Tile;
+use crate::{Vec2d, ZoomError};
+
+use super::Encoder;
+
+const TIFF_TILE_SIZE: u32 = 512;
+
+/// Writes a tiled BigTIFF file, without keeping the whole image in memory.
+/// The tiles are compressed with deflate and written as soon as they are complete.
+/// When the file is pyramidal, it also contains the image at lower resolutions,
+/// each one half the size of the previous one.
+pub struct TiffEncoder {
+    retiler: Retiler<TiffTileSaver>,
+    tile_saver: Arc<TiffTileSaver>,
+    size: Vec2d,
+}
+
+impl TiffEncoder {
+    pub fn new(
+        destination: PathBuf,
+        size: Vec2d,
+        compression: u8,
+        pyramid: bool,
+    ) -> Result<Self, ZoomError> {
+        Self::with_tile_size(
+            destination,
+            size,
+            Vec2d::square(TIFF_TILE_SIZE),
+            compression,
+            pyramid,
+        )
+    }
+
+    fn with_tile_size(
+        destination: PathBuf,
+        size: Vec2d,
+        tile_size: Vec2d,
+        compression: u8,
+        pyramid: bool,
+    ) -> Result<Self, ZoomError> {
+        debug!("Creating a tiled tiff file at {:?}", &destination);
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(destination)?;
+        let mut writer = TiffWriter::new(BufWriter::new(file), tile_size, compression)?;
+        let tile_saver = Arc::new(TiffTileSaver::default());
+        let retiler = if pyramid {
+            Retiler::new(size, tile_size, Arc::clone(&tile_saver), 1)
+        } else {
+            Retiler::single_level(size, tile_size, Arc::clone(&tile_saver))
+        };
+        for level in 0..retiler.level_count() {
+            writer.add_level(size.ceil_div(1 << level));
+        }
+        *tile_saver.0.lock().unwrap() = Some(writer);
+        Ok(TiffEncoder {
+            retiler,
+            tile_saver,
+            size,
+        })
+    }
+}
+
+impl Encoder for TiffEncoder {
+    fn add_tile(&mut self, tile: Tile) -> io::Result<()> {
+        self.retiler.add_tile(&tile)
+    }
+
+    fn finalize(&mut self) -> io::Result<()> {
+        self.retiler.finalize();
+        let writer = self
+            .tile_saver
+            .0
+            .lock()
+            .unwrap()
+            .take()
+            .expect("Tried to finalize an image twice");
+        writer.finish()
+    }
+
+    fn size(&self) -> Vec2d {
+        self.size
+    }
+}
+
+#[derive(Default)]
+struct TiffTileSaver(Mutex<Option<TiffWriter<BufWriter<File>>>>);
+
+impl TileSaver for TiffTileSaver {
+    fn save_tile(&self, _size: Vec2d, scale_factor: u32, tile: Tile) -> io::Result<()> {
+        let level = scale_factor.trailing_zeros() as usize;
+        let position = tile.position / scale_factor;
+        self.0
+            .lock()
+            .unwrap()
+            .as_mut()
+            .expect("Tried to add a tile to a finalized image")
+            .write_tile(level, position, &tile.image)
+    }
+}
+
+/// The tiles of one resolution of the image
+struct Level {
+    size: Vec2d,
+    tiles_across: u32,
+    offsets: Vec<u64>,
+    byte_counts: Vec<u64>,
+}
+
+/// Low-level writer for a BigTIFF file. Tiles are written in any order,
+/// and the image directories, that reference them, are written at the end of the file.
+struct TiffWriter<W: Write + Seek> {
+    writer: W,
+    position: u64,
+    tile_size: Vec2d,
+    compression: Compression,
+    levels: Vec<Level>,
+}
+
+const FIRST_IFD_POINTER: u64 = 8;
+
+impl<W: Write + Seek> TiffWriter<W> {
+    fn new(mut writer: W, tile_size: Vec2d, compression: u8) -> io::Result<Self> {
+        // Little-endian BigTIFF, with 8-byte offsets, and the first IFD offset to be filled later
+        writer.write_all(b"II")?;
+        writer.write_all(&43u16.to_le_bytes())?;
+        writer.write_all(&8u16.to_le_bytes())?;
+        writer.write_all(&0u16.to_le_bytes())?;
+        writer.write_all(&0u64.to_le_bytes())?;
+        let level = 1 + u32::from(compression.min(100)) * 8 / 100;
+        Ok(TiffWriter {
+            writer,
+            position: 16,
+            tile_size,
+            compression: Compression::new(level),
+            levels: vec![],
+        })
+    }
+
+    fn add_level(&mut self, size: Vec2d) {
+        let Vec2d { x, y } = size.ceil_div(self.tile_size);
+        let tiles = (x * y) as usize;
+        self.levels.push(Level {
+            size,
+            tiles_across: x,
+            offsets: vec![0; tiles],
+            byte_counts: vec![0; tiles],
+        });
+    }
+
+    fn write_tile(
+        &mut self,
+        level: usize,
+        position: Vec2d,
+        image: &DynamicImage,
+    ) -> io::Result<()> {
+        let Vec2d { x, y } = position / self.tile_size;
+        let index = (y * self.levels[level].tiles_across + x) as usize;
+        let (offset, byte_count) = self.write_tile_data(image)?;
+        let level = &mut self.levels[level];
+        level.offsets[index] = offset;
+        level.byte_counts[index] = byte_count;
+        Ok(())
+    }
+
+    /// Tiles in a tiff file all have the same size, so the tiles on the edges are padded
+    fn write_tile_data(&mut self, image: &DynamicImage) -> io::Result<(u64, u64)> {
+        let mut padded = RgbImage::new(self.tile_size.x, self.tile_size.y);
+        padded
+            .copy_from(&image.to_rgb8(), 0, 0)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "tile too large"))?;
+        let mut encoder = ZlibEncoder::new(Vec::new(), self.compression);
+        encoder.write_all(padded.as_raw())?;
+        let data = encoder.finish()?;
+        let offset = self.position;
+        self.writer.write_all(&data)?;
+        self.position += data.len() as u64;
+        Ok((offset, data.len() as u64))
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        if self.levels.iter().any(|l| l.byte_counts.contains(&0)) {
+            debug!("Filling the missing tiles of the tiff file with a blank tile");
+            let blank = DynamicImage::new_rgb8(self.tile_size.x, self.tile_size.y);
+            let (offset, byte_count) = self.write_tile_data(&blank)?;
+            for level in &mut self.levels {
+                for (o, b) in level.offsets.iter_mut().zip(&mut level.byte_counts) {
+                    if *b == 0 {
+                        (*o, *b) = (offset, byte_count);
+                    }
+                }
+            }
+        }
+        let mut next_ifd_pointer = FIRST_IFD_POINTER;
+        let levels = std::mem::take(&mut self.levels);
+        for (i, level) in levels.iter().enumerate() {
+            let ifd_offset = self.write_ifd(level, i > 0)?;
+            self.writer.seek(SeekFrom::Start(next_ifd_pointer))?;
+            self.writer.write_all(&ifd_offset.to_le_bytes())?;
+            self.writer.seek(SeekFrom::Start(self.position))?;
+            // The pointer to the next IFD is the last field of this one
+            next_ifd_pointer = self.position - 8;
+        }
+        self.writer.flush()
+    }
+
+    /// Write an image file directory, and return its offset
+    fn write_ifd(&mut self, level: &Level, reduced: bool) -> io::Result<u64> {
+        let offsets = self.write_array(&level.offsets)?;
+        let byte_counts = self.write_array(&level.byte_counts)?;
+        let tile_count = level.offsets.len() as u64;
+        let rgb_bits = 8 | (8 << 16) | (8 << 32);
+        let entries: [(u16, u16, u64, u64); 12] = [
+            (254, LONG, 1, u64::from(reduced)),          // NewSubfileType
+            (256, LONG, 1, u64::from(level.size.x)),     // ImageWidth
+            (257, LONG, 1, u64::from(level.size.y)),     // ImageLength
+            (258, SHORT, 3, rgb_bits),                   // BitsPerSample
+            (259, SHORT, 1, 8),                          // Compression: deflate
+            (262, SHORT, 1, 2),                          // PhotometricInterpretation: RGB
+            (277, SHORT, 1, 3),                          // SamplesPerPixel
+            (284, SHORT, 1, 1),                          // PlanarConfiguration: chunky
+            (322, LONG, 1, u64::from(self.tile_size.x)), // TileWidth
+            (323, LONG, 1, u64::from(self.tile_size.y)), // TileLength
+            (324, LONG8, tile_count, offsets),           // TileOffsets
+            (325, LONG8, tile_count, byte_counts),       // TileByteCounts
+        ];
+        let ifd_offset = self.position;
+        let mut ifd = Vec::with_capacity(8 + 20 * entries.len() + 8);
+        ifd.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+        for (tag, field_type, count, value) in entries {
+            ifd.extend_from_slice(&tag.to_le_bytes());
+            ifd.extend_from_slice(&field_type.to_le_bytes());
+            ifd.extend_from_slice(&count.to_le_bytes());
+            ifd.extend_from_slice(&value.to_le_bytes());
+        }
+        ifd.extend_from_slice(&0u64.to_le_bytes());
+        self.writer.write_all(&ifd)?;
+        self.position += ifd.len() as u64;
+        Ok(ifd_offset)
+    }
+
+    /// Write an array of 64-bit values. An array with a single value is stored inline in its IFD entry.
+    fn write_array(&mut self, values: &[u64]) -> io::Result<u64> {
+        if let [value] = values {
+            return Ok(*value);
+        }
+        let offset = self.position;
+        for value in values {
+            self.writer.write_all(&value.to_le_bytes())?;
+        }
+        self.position += 8 * values.len() as u64;
+        Ok(offset)
+    }
+}
+
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+const LONG8: u16 = 16;
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use image::Rgb;
+    use tiff::decoder::{Decoder, DecodingResult};
+
+    use super::*;
+    use crate::encoder::test_utils::encode_gradient;
+
+    #[test]
+    fn test_tiff_tiles() {
+        let size = Vec2d { x: 40, y: 20 };
+        let (_dir, destination) = encode_gradient("image.tiff", Vec2d { x: 40, y: 10 }, |path| {
+            TiffEncoder::with_tile_size(path, size, Vec2d::square(16), 5, false)
+        });
+
+        let image = image::open(&destination).unwrap().to_rgb8();
+        assert_eq!(image.dimensions(), (40, 20));
+        assert_eq!(image.get_pixel(33, 9), &Rgb([33, 9, 100]));
+        // The bottom of the image was never received
+        assert_eq!(image.get_pixel(33, 19), &Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn test_tiff_pyramid() {
+        let size = Vec2d { x: 40, y: 20 };
+        let (_dir, destination) = encode_gradient("image.tiff", size, |path| {
+            TiffEncoder::with_tile_size(path, size, Vec2d::square(16), 5, true)
+        });
+
+        let mut decoder = Decoder::new(File::open(&destination).unwrap()).unwrap();
+        let mut dimensions = vec![decoder.dimensions().unwrap()];
+        while decoder.more_images() {
+            decoder.next_image().unwrap();
+            dimensions.push(decoder.dimensions().unwrap());
+        }
+        assert_eq!(dimensions, vec![(40, 20), (20, 10), (10, 5)]);
+        let DecodingResult::U8(smallest) = decoder.read_image().unwrap() else {
+            panic!("Expected 8-bit samples")
+        };
+        assert_eq!(smallest.len(), 10 * 5 * 3);
+    }
+}
@@ -1,12 +1,11 @@
-use std::path::PathBuf;
-
 /**
 Used to receive tiles asynchronously and provide them to the encoder
 */
 use log::debug;
 use tokio::sync::mpsc;
 
-use crate::encoder::{encoder_for_name, Encoder};
+use crate::encoder::{encoder_for_output, Encoder, EncoderOptions, Output};
+use crate::region::{Rect, Region};
 use crate::tile::Tile;
 use crate::{Vec2d, ZoomError};
 use log::warn;
@@ -14,26 +13,35 @@ use log::warn;
 /// Data structure used to store tiles until the final image size is known
 pub enum TileBuffer {
     Buffering {
-        destination: PathBuf,
+        destination: Output,
         buffer: Vec<Tile>,
-        compression: u8,
+        options: EncoderOptions,
+        region: Option<Region>,
     },
     Writing {
-        destination: PathBuf,
+        destination: Output,
+        /// The part of the image that is written, if not the whole image
+        crop: Option<Rect>,
         tile_sender: mpsc::Sender<TileBufferMsg>,
         error_receiver: mpsc::Receiver<std::io::Error>,
     },
 }
 
 impl TileBuffer {
-    /// Create an encoder for an image of the given size at the path
+    /// Create an encoder for an image of the given size to the given output
     /// Errors out if the encoder cannot create files with the given extension
-    /// or at the given size
-    pub async fn new(destination: PathBuf, compression: u8) -> Result<Self, ZoomError> {
+    /// or at the given size.
+    /// When a region is given, only the part of the image inside it is encoded.
+    pub async fn new(
+        destination: Output,
+        options: EncoderOptions,
+        region: Option<Region>,
+    ) -> Result<Self, ZoomError> {
         Ok(TileBuffer::Buffering {
             destination,
             buffer: vec![],
-            compression,
+            options,
+            region,
         })
     }
 
@@ -42,16 +50,31 @@ impl TileBuffer {
             TileBuffer::Buffering {
                 buffer,
                 destination,
-                compression,
+                options,
+                region,
             } => {
-                let destination = std::mem::take(destination);
-                debug!("Creating a tile writer for an image of size {}", size);
-                let mut encoder = encoder_for_name(destination.clone(), size, *compression)?;
+                let destination = destination.clone();
+                let crop = region.map(|r| r.in_image(size));
+                if crop.map(|c| c.size.area() == 0).unwrap_or(false) {
+                    return Err(ZoomError::EmptyRegion {
+                        width: size.x,
+                        height: size.y,
+                    });
+                }
+                let encoded_size = crop.map(|c| c.size).unwrap_or(size);
+                debug!(
+                    "Creating a tile writer for an image of size {}",
+                    encoded_size
+                );
+                let mut encoder =
+                    encoder_for_output(destination.clone(), encoded_size, options.clone())?;
                 debug!("Adding buffered tiles: {:?}", buffer);
                 for tile in buffer.drain(..) {
-                    encoder.add_tile(tile)?;
+                    if let Some(tile) = crop_tile(crop, tile) {
+                        encoder.add_tile(tile)?;
+                    }
                 }
-                buffer_tiles(encoder, destination).await
+                buffer_tiles(encoder, destination, crop).await
             }
             TileBuffer::Writing { .. } => {
                 unreachable!("The size of the image can be set only once")
@@ -65,7 +88,12 @@ impl TileBuffer {
     pub async fn add_tile(&mut self, tile: Tile) {
         match self {
             TileBuffer::Buffering { buffer, .. } => buffer.push(tile),
-            TileBuffer::Writing { tile_sender, .. } => {
+            TileBuffer::Writing {
+                tile_sender, crop, ..
+            } => {
+                let Some(tile) = crop_tile(*crop, tile) else {
+                    return;
+                };
                 tile_sender
                     .send(TileBufferMsg::AddTile(tile))
                     .await
@@ -101,7 +129,7 @@ impl TileBuffer {
         result
     }
 
-    pub fn destination(&self) -> &PathBuf {
+    pub fn destination(&self) -> &Output {
         match self {
             TileBuffer::Buffering { destination, .. } => destination,
             TileBuffer::Writing { destination, .. } => destination,
@@ -115,7 +143,19 @@ pub enum TileBufferMsg {
     Close,
 }
 
-async fn buffer_tiles(mut encoder: Box<dyn Encoder>, destination: PathBuf) -> TileBuffer {
+/// Keep only the part of the tile that should be encoded
+fn crop_tile(crop: Option<Rect>, tile: Tile) -> Option<Tile> {
+    match crop {
+        Some(rect) => rect.crop_tile(&tile),
+        None => Some(tile),
+    }
+}
+
+async fn buffer_tiles(
+    mut encoder: Box<dyn Encoder>,
+    destination: Output,
+    crop: Option<Rect>,
+) -> TileBuffer {
     let (tile_sender, mut tile_receiver) = mpsc::channel(1024);
     let (error_sender, error_receiver) = mpsc::channel(1);
     tokio::spawn(async move {
@@ -144,5 +184,6 @@ async fn buffer_tiles(mut encoder: Box<dyn Encoder>, destination: PathBuf) -> Ti
         tile_sender,
         error_receiver,
         destination,
+        crop,
     }
 }
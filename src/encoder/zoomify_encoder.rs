@@ -0,0 +1,166 @@
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use log::debug;
+
+use crate::encoder::retiler::{Retiler, TileSaver};
+use crate::tile::Tile;
+use crate::zoomify::image_properties::ZoomLevelInfo;
+use crate::{Vec2d, ZoomError};
+
+use super::{write_tile, Encoder, TileFormat};
+
+pub const DEFAULT_ZOOMIFY_TILE_SIZE: u32 = 256;
+
+/// Writes a zoomify image: a folder with an `ImageProperties.xml` file,
+/// and `TileGroup` folders that each contain 256 jpeg tiles
+pub struct ZoomifyEncoder {
+    retiler: Retiler<ZoomifyTileSaver>,
+    root_path: PathBuf,
+    tile_size: u32,
+    num_tiles: u32,
+}
+
+impl ZoomifyEncoder {
+    pub fn new(
+        destination: PathBuf,
+        size: Vec2d,
+        tile_size: u32,
+        quality: u8,
+    ) -> Result<Self, ZoomError> {
+        let _ = std::fs::remove_file(&destination);
+        debug!("Creating zoomify directory at {:?}", &destination);
+        std::fs::create_dir_all(&destination)?;
+        let tile_size_2d = Vec2d::square(tile_size);
+        let levels = zoomify_levels(size, tile_size_2d);
+        let num_tiles = levels
+            .last()
+            .map(|l| l.tiles_before + l.size.ceil_div(tile_size_2d).area() as u32)
+            .unwrap_or(0);
+        let tile_saver = ZoomifyTileSaver {
+            root_path: destination.clone(),
+            levels,
+            quality,
+        };
+        Ok(ZoomifyEncoder {
+            retiler: Retiler::new(size, tile_size_2d, Arc::new(tile_saver), 1),
+            root_path: destination,
+            tile_size,
+            num_tiles,
+        })
+    }
+}
+
+/// The levels of a zoomify image, from the smallest to the largest.
+/// Each level is half the size of the next one, rounded down, as in the zoomify viewers.
+fn zoomify_levels(size: Vec2d, tile_size: Vec2d) -> Vec<ZoomLevelInfo> {
+    let mut sizes = vec![size];
+    let mut level_size = size;
+    while !level_size.fits_inside(tile_size) {
+        level_size = level_size / 2;
+        sizes.push(level_size);
+    }
+    let mut tiles_before = 0;
+    sizes
+        .into_iter()
+        .rev()
+        .map(|size| {
+            let level = ZoomLevelInfo {
+                size,
+                tile_size,
+                tiles_before,
+            };
+            tiles_before += size.ceil_div(tile_size).area() as u32;
+            level
+        })
+        .collect()
+}
+
+impl Encoder for ZoomifyEncoder {
+    fn add_tile(&mut self, tile: Tile) -> io::Result<()> {
+        self.retiler.add_tile(&tile)
+    }
+
+    fn finalize(&mut self) -> io::Result<()> {
+        self.retiler.finalize();
+        let Vec2d { x, y } = self.size();
+        let properties = format!(
+            r#"<IMAGE_PROPERTIES WIDTH="{}" HEIGHT="{}" NUMTILES="{}" NUMIMAGES="1" VERSION="1.8" TILESIZE="{}" />"#,
+            x, y, self.num_tiles, self.tile_size
+        );
+        let properties_path = self.root_path.join("ImageProperties.xml");
+        debug!("Writing zoomify metadata to {:?}", properties_path);
+        std::fs::write(properties_path, properties)
+    }
+
+    fn size(&self) -> Vec2d {
+        self.retiler.size()
+    }
+}
+
+struct ZoomifyTileSaver {
+    root_path: PathBuf,
+    levels: Vec<ZoomLevelInfo>,
+    quality: u8,
+}
+
+impl TileSaver for ZoomifyTileSaver {
+    fn save_tile(&self, _size: Vec2d, scale_factor: u32, tile: Tile) -> io::Result<()> {
+        let z = self.levels.len() - 1 - scale_factor.trailing_zeros() as usize;
+        let level = &self.levels[z];
+        let position = tile.position / scale_factor;
+        // The retiler rounds the size of the levels up, and zoomify rounds it down
+        if position.x >= level.size.x || position.y >= level.size.y {
+            return Ok(());
+        }
+        let visible = (level.size - position).min(tile.size());
+        let image = tile.image.crop_imm(0, 0, visible.x, visible.y);
+        let Vec2d { x, y } = position / level.tile_size;
+        let group_path = self
+            .root_path
+            .join(format!("TileGroup{}", level.tile_group(Vec2d { x, y })));
+        std::fs::create_dir_all(&group_path)?;
+        let image_path = group_path.join(format!("{}-{}-{}.jpg", z, x, y));
+        write_tile(&image, &image_path, TileFormat::Jpg, self.quality)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::test_utils::encode_gradient;
+    use crate::zoomify::image_properties::ImageProperties;
+
+    #[test]
+    fn test_zoomify_tree() {
+        let size = Vec2d { x: 33, y: 20 };
+        let (_dir, destination) = encode_gradient("image.zoomify", size, |path| {
+            ZoomifyEncoder::new(path, size, 16, 90)
+        });
+
+        let xml = std::fs::read(destination.join("ImageProperties.xml")).unwrap();
+        let properties: ImageProperties = serde_xml_rs::from_reader(&xml[..]).unwrap();
+        // Levels of 33x20 (3x2 tiles) and 16x10 (1 tile) pixels
+        assert_eq!(properties.num_tiles, 7);
+        let dimensions = |path: &str| {
+            image::image_dimensions(destination.join("TileGroup0").join(path)).unwrap()
+        };
+        assert_eq!(dimensions("0-0-0.jpg"), (16, 10));
+        assert_eq!(dimensions("1-2-1.jpg"), (1, 4));
+        assert!(!destination.join("TileGroup0/0-1-0.jpg").exists());
+    }
+
+    #[test]
+    fn test_tile_groups() {
+        let levels = zoomify_levels(Vec2d { x: 12000, y: 9788 }, Vec2d::square(256));
+        let total: u64 = levels
+            .iter()
+            .map(|l| l.size.ceil_div(l.tile_size).area())
+            .sum();
+        assert_eq!(total, 2477);
+        // Same as in the zoomify dezoomer tests
+        assert_eq!(levels[5].tile_group(Vec2d { x: 0, y: 14 }), 1);
+        assert_eq!(levels[5].tile_group(Vec2d { x: 0, y: 15 }), 2);
+    }
+}
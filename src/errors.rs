@@ -8,8 +8,15 @@ use tokio::sync::mpsc::error::SendError;
 custom_error! {
     pub ZoomError
     Networking{source: reqwest::Error} = "network error: {source}",
+    HttpStatus{status: u16, uri: String} = "HTTP error {status} for '{uri}'",
+    SlowDown{status: u16, retry_after_secs: u64} =
+        "The server is overloaded (HTTP {status}) and asked to slow down",
+    Offline{uri: String} = "'{uri}' is not in the cache, and cannot be downloaded in offline mode",
     Dezoomer{source: DezoomerError} = "Dezoomer error: {source}",
     NoLevels = "A zoomable image was found, but it did not contain any zoom level",
+    NoMatchingLevel{levels: usize} = "None of the {levels} zoom levels matches the requested size",
+    MissingInput = "No input image was given",
+    MissingTileCache = "The cache commands need the path to the tile cache, given with --tile-cache",
     NoTile = "Could not get any tile for the image. See https://dezoomify-rs.ophir.dev/no-tile-error",
     PartialDownload{successful_tiles: u64, total_tiles: u64, destination: String} =
         "Only {successful_tiles} tiles out of {total_tiles} could be downloaded. \
@@ -18,13 +25,35 @@ custom_error! {
     PostProcessing{source: Box<dyn Error>} = "unable to process the downloaded tile: {source}",
     Io{source: std::io::Error} = "Input/Output error: {source}",
     Yaml{source: serde_yaml::Error} = "Invalid YAML configuration file: {source}",
+    Json{source: serde_json::Error} = "Invalid JSON file: {source}",
     TileCopyError{x:u32, y:u32, twidth:u32, theight:u32, width:u32, height:u32} =
                                 "Unable to copy a {twidth}x{theight} tile \
                                  at position {x},{y} \
                                  on a canvas of size {width}x{height}",
     MalformedTileStr{tile_str: String} = "Malformed tile string: '{tile_str}' \
                                           expected 'x y url'",
+    EmptyRegion{width: u32, height: u32} = "The requested region does not contain any pixel \
+                                            of the {width}x{height} image",
+    DryRunUnsupported{level: String, reason: &'static str} = "Unable to list the tiles of \
+                                                              '{level}' without downloading them: \
+                                                              {reason}",
+    TileOverlapTooLarge{overlap: u32, tile_size: u32} = "The tile overlap ({overlap} pixels) \
+                                                         must be smaller than the tile size \
+                                                         ({tile_size} pixels)",
+    JpegTooLarge{width: u32, height: u32} = "The image is {width}x{height} pixels, but jpeg \
+                                             images cannot be wider or higher than 65535 pixels. \
+                                             Save it as png or tiff instead.",
     NoSuchDezoomer{name: String} = "No such dezoomer: {name}",
+    InvalidBatchJob{line: usize, source: serde_json::Error} = "Invalid job at line {line} \
+                                                               of the batch file: {source}",
+    InvalidSession{line: usize, source: serde_json::Error} = "Invalid http session archive \
+                                                              at line {line}: {source}",
+    NotRecorded{uri: String} = "'{uri}' was not recorded in the replayed http session",
+    Http{source: http::Error} = "Invalid http response: {source}",
+    MalformedCookieFile{line: usize} = "Invalid cookie file at line {line}: expected the Netscape \
+                                        format, with seven fields separated by tabs",
+    ResumeLevelNotFound{level: String} = "Unable to resume the download: \
+                                          the zoom level '{level}' could not be found anymore",
     InvalidHeaderName{source: header::InvalidHeaderName} = "Invalid header name: {source}",
     InvalidHeaderValue{source: header::InvalidHeaderValue} = "Invalid header value: {source}",
     AsyncError{source: tokio::task::JoinError} = "Unable get the result from a thread: {source}",
@@ -33,6 +62,49 @@ custom_error! {
     PngError{source: png::EncodingError} = "PNG encoding error: {source}",
 }
 
+impl ZoomError {
+    /// A short machine-readable name for the kind of error
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ZoomError::Networking { .. } => "networking",
+            ZoomError::HttpStatus { .. } => "http_status",
+            ZoomError::SlowDown { .. } => "slow_down",
+            ZoomError::Offline { .. } => "offline",
+            ZoomError::Dezoomer { .. } => "dezoomer",
+            ZoomError::NoLevels => "no_levels",
+            ZoomError::NoMatchingLevel { .. } => "no_matching_level",
+            ZoomError::MissingInput => "missing_input",
+            ZoomError::MissingTileCache => "missing_tile_cache",
+            ZoomError::NoTile => "no_tile",
+            ZoomError::PartialDownload { .. } => "partial_download",
+            ZoomError::Image { .. } => "image",
+            ZoomError::PostProcessing { .. } => "post_processing",
+            ZoomError::Io { .. } => "io",
+            ZoomError::Yaml { .. } => "yaml",
+            ZoomError::Json { .. } => "json",
+            ZoomError::TileCopyError { .. } => "tile_copy",
+            ZoomError::MalformedTileStr { .. } => "malformed_tile_str",
+            ZoomError::EmptyRegion { .. } => "empty_region",
+            ZoomError::DryRunUnsupported { .. } => "dry_run_unsupported",
+            ZoomError::TileOverlapTooLarge { .. } => "tile_overlap_too_large",
+            ZoomError::JpegTooLarge { .. } => "jpeg_too_large",
+            ZoomError::NoSuchDezoomer { .. } => "no_such_dezoomer",
+            ZoomError::InvalidBatchJob { .. } => "invalid_batch_job",
+            ZoomError::InvalidSession { .. } => "invalid_session",
+            ZoomError::NotRecorded { .. } => "not_recorded",
+            ZoomError::Http { .. } => "http",
+            ZoomError::MalformedCookieFile { .. } => "malformed_cookie_file",
+            ZoomError::ResumeLevelNotFound { .. } => "resume_level_not_found",
+            ZoomError::InvalidHeaderName { .. } => "invalid_header_name",
+            ZoomError::InvalidHeaderValue { .. } => "invalid_header_value",
+            ZoomError::AsyncError { .. } => "async",
+            ZoomError::BufferToImage { .. } => "buffer_to_image",
+            ZoomError::WriteError { .. } => "write",
+            ZoomError::PngError { .. } => "png",
+        }
+    }
+}
+
 custom_error! {
     pub BufferToImageError
     Image{source: image::ImageError} = "invalid image error: {source}",
@@ -63,5 +135,5 @@ pub fn make_io_err<E>(e: E) -> std::io::Error
 where
     E: Into<Box<dyn std::error::Error + Send + Sync>>,
 {
-    std::io::Error::new(std::io::ErrorKind::Other, e)
+    std::io::Error::other(e)
 }
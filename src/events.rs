@@ -0,0 +1,95 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use crate::{TileFailure, Vec2d};
+
+/// Something that happened while dezooming an image
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// The zoom levels of the image were found
+    LevelsFound {
+        source: String,
+        dezoomer: String,
+        levels: usize,
+    },
+    /// The zoom level to download was chosen
+    LevelChosen { level: String, size: Option<Vec2d> },
+    /// The download of a batch of tiles started.
+    /// Dezoomers that discover the tiles progressively produce several batches,
+    /// and `total` is the number of tiles in all the batches so far.
+    BatchStarted { tiles: u64, total: u64 },
+    /// Tiles that failed will be requested again after the given delay
+    RetryScheduled { tiles: u64, delay_ms: u64 },
+    /// A tile was downloaded. `total` grows as the dezoomer discovers new tiles.
+    TileLoaded {
+        position: Vec2d,
+        done: u64,
+        total: u64,
+    },
+    /// A tile could not be downloaded
+    TileFailed {
+        url: String,
+        failure: TileFailure,
+        cause: String,
+        will_retry: bool,
+        done: u64,
+        total: u64,
+    },
+    /// All the tiles were downloaded, and the image file is being finalized
+    EncodingStarted,
+    /// The image was written. The destination is given when the image was written to a file.
+    Finished {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        destination: Option<PathBuf>,
+        successful_tiles: u64,
+        total_tiles: u64,
+    },
+    /// A message of the command-line program's log.
+    /// With `--progress json`, log messages are printed as events, so that every line is valid JSON.
+    Log { level: String, message: String },
+}
+
+/// A function that is called with every event
+#[derive(Clone)]
+pub struct EventHandler(Arc<dyn Fn(&Event) + Send + Sync>);
+
+impl EventHandler {
+    pub fn new<F: Fn(&Event) + Send + Sync + 'static>(handler: F) -> Self {
+        EventHandler(Arc::new(handler))
+    }
+
+    pub fn handle(&self, event: &Event) {
+        (self.0)(event)
+    }
+}
+
+impl fmt::Debug for EventHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EventHandler")
+    }
+}
+
+#[test]
+fn test_event_json() {
+    let event = Event::TileLoaded {
+        position: Vec2d { x: 256, y: 0 },
+        done: 1,
+        total: 4,
+    };
+    assert_eq!(
+        serde_json::to_string(&event).unwrap(),
+        r#"{"event":"tile_loaded","position":{"x":256,"y":0},"done":1,"total":4}"#
+    );
+    let log = Event::Log {
+        level: "warn".into(),
+        message: "Retrying".into(),
+    };
+    assert_eq!(
+        serde_json::to_string(&log).unwrap(),
+        r#"{"event":"log","level":"warn","message":"Retrying"}"#
+    );
+}
@@ -0,0 +1,208 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use futures::future::BoxFuture;
+use log::debug;
+use reqwest::{NoProxy, Proxy};
+
+use crate::arguments::Arguments;
+use crate::session::{Recorder, Replayer};
+use crate::ZoomError;
+
+/// Makes the http requests of dezoomify-rs.
+/// The default implementation, [ReqwestFetcher], uses the network.
+/// Another implementation can be given in [Arguments::fetcher] to send the requests
+/// through another http stack, or to answer them from memory in tests.
+///
+/// The requests contain all the headers that should be sent, including the ones
+/// required by the dezoomer. Responses with an error status are returned normally,
+/// and an error should be returned only when no response could be obtained.
+pub trait Fetcher: Send + Sync {
+    fn fetch(
+        &self,
+        request: http::Request<()>,
+    ) -> BoxFuture<'_, Result<http::Response<Vec<u8>>, ZoomError>>;
+}
+
+impl fmt::Debug for dyn Fetcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Fetcher")
+    }
+}
+
+/// A fetcher that makes its requests with reqwest
+#[derive(Debug, Clone)]
+pub struct ReqwestFetcher {
+    client: reqwest::Client,
+}
+
+impl ReqwestFetcher {
+    pub fn new(client: reqwest::Client) -> Self {
+        ReqwestFetcher { client }
+    }
+
+    /// A reqwest client configured with the network options in the arguments
+    pub fn from_args(args: &Arguments) -> Result<Self, ZoomError> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(proxy_url) = &args.proxy {
+            debug!("Using the proxy {}", proxy_url);
+            let proxy = Proxy::all(proxy_url.as_str())?
+                .no_proxy(args.no_proxy.as_deref().and_then(NoProxy::from_string));
+            builder = builder.proxy(proxy);
+        } else if let Some(no_proxy) = &args.no_proxy {
+            // reqwest ignores the proxies of the environment as soon as a proxy is given,
+            // so they are given again with the hosts to bypass
+            let env_var = |name: &str| std::env::var(name).ok();
+            for proxy in env_proxies(env_var, no_proxy)? {
+                builder = builder.proxy(proxy);
+            }
+        }
+        let client = builder
+            .cookie_provider(args.shared_cookies.jar(args.cookies.as_deref())?)
+            .http1_title_case_headers()
+            .referer(false)
+            .pool_max_idle_per_host(args.max_idle_per_host)
+            .danger_accept_invalid_certs(args.accept_invalid_certs)
+            .timeout(args.timeout)
+            .build()?;
+        Ok(ReqwestFetcher { client })
+    }
+}
+
+/// The proxies configured in the environment variables, that are not used for the given hosts
+/// nor for the ones in the NO_PROXY variable
+fn env_proxies<F: Fn(&str) -> Option<String>>(
+    env_var: F,
+    no_proxy: &str,
+) -> Result<Vec<Proxy>, ZoomError> {
+    let var = |name: &str| {
+        env_var(&name.to_lowercase())
+            .or_else(|| env_var(name))
+            .filter(|value| !value.is_empty())
+    };
+    let hosts = match var("NO_PROXY") {
+        Some(env_hosts) => format!("{},{}", no_proxy, env_hosts),
+        None => no_proxy.to_string(),
+    };
+    let mut proxies = vec![];
+    // The first matching proxy is used, so the protocol-specific ones come first
+    for name in ["HTTP_PROXY", "HTTPS_PROXY", "ALL_PROXY"] {
+        if let Some(url) = var(name) {
+            debug!("Using the proxy {} from {}", url, name);
+            let proxy = match name {
+                "HTTP_PROXY" => Proxy::http(url)?,
+                "HTTPS_PROXY" => Proxy::https(url)?,
+                _ => Proxy::all(url)?,
+            };
+            proxies.push(proxy.no_proxy(NoProxy::from_string(&hosts)));
+        }
+    }
+    Ok(proxies)
+}
+
+impl Fetcher for ReqwestFetcher {
+    fn fetch(
+        &self,
+        request: http::Request<()>,
+    ) -> BoxFuture<'_, Result<http::Response<Vec<u8>>, ZoomError>> {
+        Box::pin(async move {
+            let (parts, ()) = request.into_parts();
+            let request = self
+                .client
+                .request(parts.method, parts.uri.to_string())
+                .headers(parts.headers)
+                .build()?;
+            let response = self.client.execute(request).await?;
+            let mut builder = http::Response::builder()
+                .status(response.status())
+                .version(response.version());
+            if let Some(headers) = builder.headers_mut() {
+                headers.extend(response.headers().clone());
+            }
+            let body = response.bytes().await?.to_vec();
+            Ok(builder.body(body)?)
+        })
+    }
+}
+
+/// The fetcher shared by all the http clients created from the same arguments.
+/// It is created the first time it is used.
+#[derive(Debug, Clone, Default)]
+pub struct SharedFetcher {
+    fetcher: Arc<Mutex<Option<Arc<dyn Fetcher>>>>,
+}
+
+impl SharedFetcher {
+    pub fn get(&self, args: &Arguments) -> Result<Arc<dyn Fetcher>, ZoomError> {
+        let mut fetcher = self.fetcher.lock().unwrap();
+        if let Some(fetcher) = &*fetcher {
+            return Ok(Arc::clone(fetcher));
+        }
+        let created: Arc<dyn Fetcher> = if let Some(path) = &args.replay {
+            Arc::new(Replayer::open(path)?)
+        } else {
+            let inner = match &args.fetcher {
+                Some(fetcher) => Arc::clone(fetcher),
+                None => Arc::new(ReqwestFetcher::from_args(args)?),
+            };
+            match &args.record {
+                Some(path) => Arc::new(Recorder::create(path, inner)?),
+                None => inner,
+            }
+        };
+        *fetcher = Some(Arc::clone(&created));
+        Ok(created)
+    }
+}
+
+#[tokio::test]
+async fn test_no_proxy_with_env_proxies() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // A server that answers every request with its request line,
+    // so that the requests it receives as a proxy can be recognized
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let mut request = vec![];
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            let line = String::from_utf8_lossy(&request);
+            let line = line.lines().next().unwrap();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                line.len(),
+                line
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+        }
+    });
+
+    let proxy_url = format!("http://127.0.0.1:{}", port);
+    let env_var = |name: &str| match name {
+        "http_proxy" => Some(proxy_url.clone()),
+        "NO_PROXY" => Some("example.org".to_string()),
+        _ => None,
+    };
+    let mut builder = reqwest::Client::builder();
+    for proxy in env_proxies(env_var, "localhost").unwrap() {
+        builder = builder.proxy(proxy);
+    }
+    let client = builder.build().unwrap();
+    let request_line = |url: String| {
+        let client = client.clone();
+        async move { client.get(url).send().await.unwrap().text().await.unwrap() }
+    };
+    let proxied = format!("http://127.0.0.1:{}/", port);
+    assert_eq!(
+        request_line(proxied.clone()).await,
+        format!("GET {} HTTP/1.1", proxied)
+    );
+    let direct = format!("http://localhost:{}/", port);
+    assert_eq!(request_line(direct).await, "GET / HTTP/1.1");
+    assert_eq!(env_proxies(|_| None, "localhost").unwrap().len(), 0);
+}
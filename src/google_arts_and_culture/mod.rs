@@ -88,7 +88,7 @@ impl TilesRect for GAPZoomLevel {
     }
 
     fn post_process_fn(&self) -> PostProcessFn {
-        PostProcessFn::Fn(post_process_tile)
+        PostProcessFn::new(post_process_tile)
     }
 
     fn title(&self) -> Option<String> {
@@ -100,7 +100,7 @@ fn post_process_tile(
     _tile: &TileReference,
     data: Vec<u8>,
 ) -> Result<Vec<u8>, Box<dyn Error + Send + 'static>> {
-    decryption::decrypt(data).map_err(|e| Box::new(e) as Box<(dyn Error + Send + 'static)>)
+    decryption::decrypt(data).map_err(|e| Box::new(e) as Box<dyn Error + Send + 'static>)
 }
 
 impl std::fmt::Debug for GAPZoomLevel {
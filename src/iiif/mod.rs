@@ -180,7 +180,7 @@ impl std::fmt::Debug for IIIFZoomLevel {
         let name = self
             .base_url
             .split('/')
-            .last()
+            .next_back()
             .and_then(|s: &str| {
                 let s = s.trim();
                 if s.is_empty() {
@@ -66,7 +66,7 @@ impl ImageInfo {
         }
     }
 
-    fn profile_info(&self) -> Cow<ProfileInfo> {
+    fn profile_info(&self) -> Cow<'_, ProfileInfo> {
         self.profile
             .as_ref()
             .map(|p| p.profile_info())
@@ -266,7 +266,7 @@ lazy_static! {
 }
 
 impl Profile {
-    fn profile_info(&self) -> Cow<ProfileInfo> {
+    fn profile_info(&self) -> Cow<'_, ProfileInfo> {
         match self {
             Profile::Reference(s) => {
                 PROFILE_REFERENCES
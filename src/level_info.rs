@@ -0,0 +1,80 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::dezoomer::ZoomLevel;
+use crate::Vec2d;
+
+/// A description of all the zoom levels found for an image, without downloading any tile
+#[derive(Debug, Serialize)]
+pub struct ImageInfo {
+    /// The URI the zoom levels were loaded from
+    pub source: String,
+    /// The name of the dezoomer that found the zoom levels
+    pub dezoomer: String,
+    pub levels: Vec<LevelInfo>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct LevelInfo {
+    pub name: String,
+    pub title: Option<String>,
+    pub size: Option<Vec2d>,
+    pub tile_size: Option<Vec2d>,
+    pub tile_count: Option<u64>,
+    /// The http headers that will be used when requesting the tiles
+    pub http_headers: BTreeMap<String, String>,
+}
+
+impl LevelInfo {
+    /// Describe a zoom level. The given headers override the ones of the level.
+    pub fn new<'a, I: Iterator<Item = (&'a String, &'a String)>>(
+        level: &ZoomLevel,
+        headers: I,
+    ) -> Self {
+        let http_headers = level
+            .http_headers()
+            .into_iter()
+            .chain(headers.map(|(k, v)| (k.clone(), v.clone())))
+            .collect();
+        LevelInfo {
+            name: level.name(),
+            title: level.title(),
+            size: level.size_hint(),
+            tile_size: level.tile_size_hint(),
+            tile_count: level.tile_count_hint(),
+            http_headers,
+        }
+    }
+}
+
+#[test]
+fn test_level_info() {
+    use crate::dezoomer::TilesRect;
+
+    #[derive(Debug)]
+    struct FakeLvl;
+
+    impl TilesRect for FakeLvl {
+        fn size(&self) -> Vec2d {
+            Vec2d { x: 100, y: 50 }
+        }
+
+        fn tile_size(&self) -> Vec2d {
+            Vec2d { x: 30, y: 30 }
+        }
+
+        fn tile_url(&self, pos: Vec2d) -> String {
+            format!("http://x.com/{},{}", pos.x, pos.y)
+        }
+    }
+
+    let level: ZoomLevel = Box::new(FakeLvl);
+    let headers = [("Cookie".to_string(), "a=b".to_string())];
+    let info = LevelInfo::new(&level, headers.iter().map(|(k, v)| (k, v)));
+    assert_eq!(info.size, Some(Vec2d { x: 100, y: 50 }));
+    assert_eq!(info.tile_size, Some(Vec2d { x: 30, y: 30 }));
+    assert_eq!(info.tile_count, Some(8));
+    assert_eq!(info.http_headers["Referer"], "http://x.com/0,0");
+    assert_eq!(info.http_headers["Cookie"], "a=b");
+}
@@ -1,38 +1,62 @@
 #![allow(clippy::upper_case_acronyms)]
 
+use std::collections::HashMap;
 use std::env::current_dir;
 use std::error::Error;
-use std::io::BufRead;
-use std::path::PathBuf;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::{fmt, fs, io};
 
 use futures::stream::StreamExt;
-use indicatif::{ProgressBar, ProgressStyle};
 use itertools::Itertools;
-use log::{debug, info};
-use reqwest::Client;
+use log::{debug, info, warn};
 
-pub use arguments::Arguments;
+pub use arguments::{Arguments, CacheCommand, Command, ProgressMode};
+pub use auto::DezoomerRegistry;
+pub use batch::{dezoomify_batch, BatchReport, JobReport, JobResult};
+pub use builder::{DezoomifyBuilder, Job, LevelSelection};
+pub use cache::{CacheEntry, CacheStore, CachedImage, GcReport};
 use dezoomer::TileReference;
 use dezoomer::{Dezoomer, DezoomerError, DezoomerInput, ZoomLevels};
 use dezoomer::{TileFetchResult, ZoomLevel, ZoomLevelIter};
+pub use encoder::{EncoderOptions, IiifVersion, MemoryImage, Output, PixelWriter, TileFormat};
 pub use errors::ZoomError;
-use network::{client, fetch_uri};
+pub use events::{Event, EventHandler};
+pub use fetcher::{Fetcher, ReqwestFetcher};
+pub use level_info::{ImageInfo, LevelInfo};
+pub use manifest::{DownloadManifest, TileStatus};
+use network::client;
+pub use network::HttpClient;
 use output_file::get_outname;
+pub use retry::{RetryPolicy, TileFailure};
 use tile::Tile;
 pub use vec2d::Vec2d;
 
+use crate::cache::FileCache;
 use crate::dezoomer::PageContents;
 use crate::encoder::tile_buffer::TileBuffer;
 use crate::network::TileDownloader;
 use crate::output_file::reserve_output_file;
+use crate::progress::Progress;
 
 mod arguments;
+mod batch;
+mod builder;
+mod cache;
+mod cookies;
 pub mod dezoomer;
 mod encoder;
 mod errors;
+mod events;
+mod fetcher;
+mod level_info;
+mod manifest;
 mod network;
 mod output_file;
+mod progress;
+pub mod region;
+mod retry;
+mod session;
 pub mod tile;
 mod vec2d;
 
@@ -48,6 +72,7 @@ pub mod krpano;
 pub mod nypl;
 pub mod pff;
 mod throttler;
+pub mod tile_list;
 pub mod zoomify;
 
 fn stdin_line() -> Result<String, ZoomError> {
@@ -62,7 +87,8 @@ fn stdin_line() -> Result<String, ZoomError> {
 
 async fn list_tiles(
     dezoomer: &mut dyn Dezoomer,
-    http: &Client,
+    http: &HttpClient,
+    cache: &FileCache,
     uri: &str,
 ) -> Result<ZoomLevels, ZoomError> {
     let mut i = DezoomerInput {
@@ -73,7 +99,7 @@ async fn list_tiles(
         match dezoomer.zoom_levels(&i) {
             Ok(levels) => return Ok(levels),
             Err(DezoomerError::NeedsData { uri }) => {
-                let contents = fetch_uri(&uri, http).await.into();
+                let contents = cache.fetch(&uri, http).await.into();
                 debug!("Response for metadata file '{}': {:?}", uri, &contents);
                 i.uri = uri;
                 i.contents = contents;
@@ -115,87 +141,282 @@ fn choose_level(mut levels: Vec<ZoomLevel>, args: &Arguments) -> Result<ZoomLeve
                 });
             if let Some((i, _)) = pos {
                 Ok(levels.swap_remove(i))
-            } else {
+            } else if args.interactive {
                 level_picker(levels)
+            } else {
+                Err(ZoomError::NoMatchingLevel {
+                    levels: levels.len(),
+                })
             }
         }
     }
 }
 
-fn progress_bar(n: usize) -> ProgressBar {
-    let progress = ProgressBar::new(n as u64);
-    progress.set_style(
-        ProgressStyle::default_bar()
-            .template("[ETA:{eta}] {bar:40.cyan/blue} {pos:>4}/{len:4} {msg}")
-            .expect("Invalid indicatif progress bar template")
-            .progress_chars("##-"),
+async fn find_zoomlevels(
+    dezoomer: &mut dyn Dezoomer,
+    args: &Arguments,
+    cache_root: Option<&Path>,
+    uri: &str,
+) -> Result<ZoomLevels, ZoomError> {
+    let http_client = client(args.headers(), args, Some(uri))?;
+    let cache = args.file_cache(cache_root)?.for_image(uri, args.headers());
+    info!("Trying to locate a zoomable image...");
+    let zoom_levels: Vec<ZoomLevel> = list_tiles(dezoomer, &http_client, &cache, uri).await?;
+    info!("Found {} zoom levels", zoom_levels.len());
+    args.emit(Event::LevelsFound {
+        source: uri.to_string(),
+        dezoomer: dezoomer.found_by().to_string(),
+        levels: zoom_levels.len(),
+    });
+    Ok(zoom_levels)
+}
+
+/// Find the zoom level that was being downloaded when the given manifest was written
+async fn find_resumed_zoomlevel(
+    args: &Arguments,
+    manifest: &DownloadManifest,
+) -> Result<ZoomLevel, ZoomError> {
+    let mut dezoomer = args.dezoomer_by_name(&manifest.dezoomer)?;
+    let cache_root = manifest.tile_cache.as_deref();
+    find_zoomlevels(dezoomer.as_mut(), args, cache_root, &manifest.source)
+        .await?
+        .into_iter()
+        .find(|level| level.name() == manifest.level)
+        .ok_or_else(|| ZoomError::ResumeLevelNotFound {
+            level: manifest.level.clone(),
+        })
+}
+
+async fn resume_manifest(
+    args: &Arguments,
+    manifest_path: &Path,
+) -> Result<(ZoomLevel, DownloadManifest), ZoomError> {
+    let mut manifest = DownloadManifest::load(manifest_path)?;
+    info!(
+        "Resuming the download of {:?}. {} tiles were missing.",
+        manifest.destination,
+        manifest.failed_tiles().count()
     );
-    progress
+    if args.tile_storage_folder.is_some() {
+        manifest.tile_cache = args.tile_storage_folder.clone();
+    }
+    let zoom_level = find_resumed_zoomlevel(args, &manifest).await?;
+    if args.region.is_some() {
+        manifest.region = args.region;
+    }
+    manifest.tiles.clear();
+    Ok((zoom_level, manifest))
 }
 
-async fn find_zoomlevel(args: &Arguments) -> Result<ZoomLevel, ZoomError> {
+/// Find the zoom level to download, and describe its download in a new manifest.
+/// When the image is not written to a file, the manifest has an empty destination.
+async fn new_manifest(
+    args: &Arguments,
+    to_file: bool,
+) -> Result<(ZoomLevel, DownloadManifest), ZoomError> {
     let mut dezoomer = args.find_dezoomer()?;
     let uri = args.choose_input_uri()?;
-    let http_client = client(args.headers(), args, Some(&uri))?;
-    info!("Trying to locate a zoomable image...");
-    let zoom_levels: Vec<ZoomLevel> = list_tiles(dezoomer.as_mut(), &http_client, &uri).await?;
-    info!("Found {} zoom levels", zoom_levels.len());
-    choose_level(zoom_levels, args)
+    let zoom_levels = find_zoomlevels(
+        dezoomer.as_mut(),
+        args,
+        args.tile_storage_folder.as_deref(),
+        &uri,
+    )
+    .await?;
+    let zoom_level = choose_level(zoom_levels, args)?;
+    let save_as = if to_file {
+        output_path(args, &zoom_level)?
+    } else {
+        PathBuf::new()
+    };
+    let manifest = DownloadManifest::new(
+        uri,
+        dezoomer.name().to_string(),
+        zoom_level.name(),
+        args.region,
+        save_as,
+        args.tile_storage_folder.clone(),
+    );
+    Ok((zoom_level, manifest))
 }
 
-pub async fn dezoomify(args: &Arguments) -> Result<PathBuf, ZoomError> {
-    let zoom_level = find_zoomlevel(args).await?;
+/// Choose the path of the output image and create an empty file there
+fn output_path(args: &Arguments, zoom_level: &ZoomLevel) -> Result<PathBuf, ZoomError> {
     let base_dir = current_dir()?;
-    let outname = get_outname(
-        &args.outfile,
-        &zoom_level.title(),
-        &base_dir,
-        zoom_level.size_hint(),
-    );
+    let size_hint = zoom_level
+        .size_hint()
+        .map(|size| args.region.map(|r| r.in_image(size).size).unwrap_or(size));
+    let outname = get_outname(&args.outfile, &zoom_level.title(), &base_dir, size_hint);
     let save_as = fs::canonicalize(outname.as_path()).unwrap_or_else(|_e| outname.clone());
     reserve_output_file(&save_as)?;
-    let tile_buffer: TileBuffer = TileBuffer::new(save_as.clone(), args.compression).await?;
-    info!("Dezooming {}", zoom_level.name());
-    dezoomify_level(args, zoom_level, tile_buffer).await?;
     Ok(save_as)
 }
 
+/// List all the zoom levels available for the input image, without downloading anything
+pub async fn describe(args: &Arguments) -> Result<ImageInfo, ZoomError> {
+    let mut dezoomer = args.find_dezoomer()?;
+    let uri = args.choose_input_uri()?;
+    let zoom_levels = find_zoomlevels(
+        dezoomer.as_mut(),
+        args,
+        args.tile_storage_folder.as_deref(),
+        &uri,
+    )
+    .await?;
+    let levels = zoom_levels
+        .iter()
+        .map(|level| LevelInfo::new(level, args.headers()))
+        .collect();
+    Ok(ImageInfo {
+        source: uri,
+        dezoomer: dezoomer.found_by().to_string(),
+        levels,
+    })
+}
+
+/// Write the references of all the tiles of the chosen zoom level, in the "x y url" format,
+/// without downloading them. The tile list is written to stdout if the destination is "-".
+/// Only the tiles of the region are listed, if one is given.
+/// Fails when the tiles are only discovered while downloading them.
+/// Returns the number of tiles.
+pub async fn dry_run(args: &Arguments, destination: &Path) -> Result<u64, ZoomError> {
+    let mut dezoomer = args.find_dezoomer()?;
+    let uri = args.choose_input_uri()?;
+    let zoom_levels = find_zoomlevels(
+        dezoomer.as_mut(),
+        args,
+        args.tile_storage_folder.as_deref(),
+        &uri,
+    )
+    .await?;
+    let mut zoom_level = choose_level(zoom_levels, args)?;
+    // Some dezoomers discover the layout of the tiles by downloading them:
+    // their first batch of tiles is not the complete list
+    let Some(tile_count) = zoom_level.tile_count_hint() else {
+        return Err(ZoomError::DryRunUnsupported {
+            level: zoom_level.name(),
+            reason: "the tiles of this image are discovered while they are downloaded",
+        });
+    };
+    let size = zoom_level.size_hint();
+    let mut tile_refs = zoom_level.next_tiles(None);
+    if tile_refs.len() as u64 != tile_count {
+        return Err(ZoomError::DryRunUnsupported {
+            level: zoom_level.name(),
+            reason: "the tiles of this image are discovered while they are downloaded",
+        });
+    }
+    if let Some(region) = args.region {
+        let Some(size) = size else {
+            return Err(ZoomError::DryRunUnsupported {
+                level: zoom_level.name(),
+                reason: "the size of the image is needed to select the tiles of the region",
+            });
+        };
+        tile_refs = region.in_image(size).filter_tiles(tile_refs, size);
+    }
+    let mut writer: Box<dyn Write> = if destination == Path::new("-") {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(BufWriter::new(fs::File::create(destination)?))
+    };
+    tile_list::write_header(&mut writer, &zoom_level.name(), size)?;
+    for tile_ref in tile_refs.iter() {
+        tile_list::write_tile(&mut writer, tile_ref)?;
+    }
+    writer.flush()?;
+    Ok(tile_refs.len() as u64)
+}
+
+pub async fn dezoomify(args: &Arguments) -> Result<PathBuf, ZoomError> {
+    let (zoom_level, mut manifest) = match &args.resume {
+        Some(manifest_path) => resume_manifest(args, manifest_path).await?,
+        None => new_manifest(args, true).await?,
+    };
+    let output = Output::File(manifest.destination.clone());
+    match download_level(args, zoom_level, output, &mut manifest).await {
+        Ok(()) => manifest.remove(),
+        Err(err @ ZoomError::PartialDownload { .. }) => {
+            let manifest_path = manifest.save()?;
+            if manifest.tile_cache.is_none() {
+                warn!(
+                    "No tile cache was used (see --tile-cache), \
+                    so resuming this download will require downloading all the tiles again."
+                );
+            }
+            warn!(
+                "The list of missing tiles was saved to {:?}. \
+                You can retry downloading them with: --resume {:?}",
+                manifest_path, manifest_path
+            );
+            return Err(err);
+        }
+        Err(err) => return Err(err),
+    }
+    Ok(manifest.destination)
+}
+
+/// Download the image to the given output instead of a file chosen from the arguments.
+/// Since the output is not a file, no manifest is written when some tiles fail,
+/// and `args.resume` is ignored.
+pub async fn dezoomify_to(args: &Arguments, output: Output) -> Result<(), ZoomError> {
+    let (zoom_level, mut manifest) = new_manifest(args, false).await?;
+    download_level(args, zoom_level, output, &mut manifest).await
+}
+
+async fn download_level(
+    args: &Arguments,
+    zoom_level: ZoomLevel,
+    output: Output,
+    manifest: &mut DownloadManifest,
+) -> Result<(), ZoomError> {
+    let tile_buffer = TileBuffer::new(output, args.encoder_options(), manifest.region).await?;
+    info!("Dezooming {}", zoom_level.name());
+    args.emit(Event::LevelChosen {
+        level: zoom_level.name(),
+        size: zoom_level.size_hint(),
+    });
+    dezoomify_level(args, zoom_level, tile_buffer, manifest).await
+}
+
 pub async fn dezoomify_level(
     args: &Arguments,
     mut zoom_level: ZoomLevel,
     tile_buffer: TileBuffer,
+    manifest: &mut DownloadManifest,
 ) -> Result<(), ZoomError> {
     let level_headers = zoom_level.http_headers();
     let downloader = TileDownloader {
         http_client: client(level_headers.iter().chain(args.headers()), args, None)?,
         post_process_fn: zoom_level.post_process_fn(),
-        retries: args.retries,
-        retry_delay: args.retry_delay,
-        tile_storage_folder: args.tile_storage_folder.clone(),
+        cache: args
+            .file_cache(manifest.tile_cache.as_deref())?
+            .for_image(&manifest.source, level_headers.iter().chain(args.headers())),
+        throttler: throttler::Throttler::new(args.min_interval, args.parallelism),
     };
-    let mut throttler = throttler::Throttler::new(args.min_interval);
+    let retry_policy = args.retry_policy();
     info!("Creating canvas");
     let mut canvas = tile_buffer;
 
-    let progress = progress_bar(10);
+    let progress = Progress::new(args);
     let mut total_tiles = 0u64;
     let mut successful_tiles = 0u64;
-
-    progress.set_message("Computing the URLs of the image tiles...");
+    // Tiles that were downloaded or that failed definitively
+    let mut done_tiles = 0u64;
 
     let mut zoom_level_iter = ZoomLevelIter::new(&mut zoom_level);
     let mut last_count = 0;
     let mut last_successes = 0;
-    while let Some(tile_refs) = zoom_level_iter.next_tile_references() {
+    while let Some(mut tile_refs) = zoom_level_iter.next_tile_references() {
+        if let (Some(region), Some(size)) = (manifest.region, zoom_level_iter.size_hint()) {
+            tile_refs = region.in_image(size).filter_tiles(tile_refs, size);
+        }
         last_count = tile_refs.len() as u64;
         total_tiles += last_count;
-        progress.set_length(total_tiles);
-
-        progress.set_message("Requesting the tiles...");
-
-        let mut stream = futures::stream::iter(tile_refs)
-            .map(|tile_ref: TileReference| downloader.download_tile(tile_ref))
-            .buffer_unordered(args.parallelism);
+        progress.emit(Event::BatchStarted {
+            tiles: last_count,
+            total: total_tiles,
+        });
 
         last_successes = 0;
         let mut tile_size = None;
@@ -204,33 +425,79 @@ pub async fn dezoomify_level(
             canvas.set_size(size).await?;
         }
 
-        while let Some(tile_result) = stream.next().await {
-            debug!("Received tile result: {:?}", tile_result);
-            progress.inc(1);
-            let tile = match tile_result {
-                Ok(tile) => {
-                    progress.set_message(format!("Loaded tile at {}", tile.position()));
-                    tile_size.replace(tile.size());
-                    last_successes += 1;
-                    Some(tile)
-                }
-                Err(err) => {
-                    // If a tile download fails, we replace it with an empty tile
-                    progress.set_message(err.to_string());
-                    let position = err.tile_reference.position;
-                    tile_size.and_then(|tile_size| {
-                        zoom_level_iter.size_hint().map(|canvas_size| {
-                            let size = max_size_in_rect(position, tile_size, canvas_size);
-                            Tile::empty(position, size)
-                        })
-                    })
-                }
+        // Tiles that failed with a transient error are put in a queue, and retried
+        // once all the other tiles of the batch have been requested
+        let mut failed_tiles = HashMap::new();
+        let mut pending = tile_refs.clone();
+        let mut attempt = 0;
+        while !pending.is_empty() {
+            let parallelism = if attempt == 0 {
+                args.parallelism
+            } else {
+                let wait_time = args.retry_delay * 2u32.saturating_pow(attempt as u32 - 1);
+                progress.emit(Event::RetryScheduled {
+                    tiles: pending.len() as u64,
+                    delay_ms: wait_time.as_millis() as u64,
+                });
+                tokio::time::sleep(wait_time).await;
+                args.retry_parallelism.unwrap_or(args.parallelism)
             };
-            if let Some(tile) = tile {
-                canvas.add_tile(tile).await;
+            let can_retry = attempt < args.retries;
+            let mut retry_queue = vec![];
+            let mut stream = futures::stream::iter(pending)
+                .map(|tile_ref: TileReference| downloader.download_tile(tile_ref))
+                .buffer_unordered(parallelism.max(1));
+
+            while let Some(tile_result) = stream.next().await {
+                debug!("Received tile result: {:?}", tile_result);
+                match tile_result {
+                    Ok(tile) => {
+                        done_tiles += 1;
+                        progress.emit(Event::TileLoaded {
+                            position: tile.position(),
+                            done: done_tiles,
+                            total: total_tiles,
+                        });
+                        tile_size.replace(tile.size());
+                        last_successes += 1;
+                        canvas.add_tile(tile).await;
+                    }
+                    Err(err) => {
+                        let will_retry = can_retry && retry_policy.should_retry(err.failure);
+                        if will_retry {
+                            warn!("{}. Retrying it later.", err);
+                        } else {
+                            done_tiles += 1;
+                        }
+                        progress.emit(Event::TileFailed {
+                            url: err.tile_reference.url.clone(),
+                            failure: err.failure,
+                            cause: err.to_string(),
+                            will_retry,
+                            done: done_tiles,
+                            total: total_tiles,
+                        });
+                        if will_retry {
+                            retry_queue.push(err.tile_reference);
+                        } else {
+                            failed_tiles.insert(err.tile_reference, err.failure);
+                        }
+                    }
+                }
             }
-            throttler.wait().await;
+            pending = retry_queue;
+            attempt += 1;
         }
+
+        // Failed tiles are replaced with empty tiles
+        if let (Some(tile_size), Some(canvas_size)) = (tile_size, zoom_level_iter.size_hint()) {
+            for tile_ref in failed_tiles.keys() {
+                let position = tile_ref.position;
+                let size = max_size_in_rect(position, tile_size, canvas_size);
+                canvas.add_tile(Tile::empty(position, size)).await;
+            }
+        }
+        manifest.add_batch(&tile_refs, &failed_tiles);
         successful_tiles += last_successes;
         zoom_level_iter.set_fetch_result(TileFetchResult {
             count: last_count,
@@ -239,17 +506,28 @@ pub async fn dezoomify_level(
         });
     }
 
+    if let Some(summary) = manifest.failure_summary() {
+        warn!("Some tiles could not be downloaded: {}", summary);
+    }
+
     if successful_tiles == 0 {
         return Err(ZoomError::NoTile);
     }
 
-    progress.set_message("Downloaded all tiles. Finalizing the image file.");
+    progress.emit(Event::EncodingStarted);
     canvas.finalize().await?;
 
-    progress.finish_with_message("Finished tile download");
+    progress.emit(Event::Finished {
+        destination: match canvas.destination() {
+            Output::File(path) => Some(path.clone()),
+            _ => None,
+        },
+        successful_tiles,
+        total_tiles,
+    });
 
     if last_successes < last_count {
-        let destination = canvas.destination().to_string_lossy().to_string();
+        let destination = canvas.destination().to_string();
         Err(ZoomError::PartialDownload {
             successful_tiles,
             total_tiles,
@@ -263,9 +541,17 @@ pub async fn dezoomify_level(
 #[derive(Debug)]
 pub struct TileDownloadError {
     tile_reference: TileReference,
+    failure: TileFailure,
     cause: ZoomError,
 }
 
+impl TileDownloadError {
+    /// Why the tile could not be downloaded
+    pub fn failure(&self) -> TileFailure {
+        self.failure
+    }
+}
+
 impl fmt::Display for TileDownloadError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -1,7 +1,13 @@
+use std::io::Write;
+use std::time::{Duration, UNIX_EPOCH};
+
 use colour::{green_ln, red_ln, yellow_ln};
 use human_panic::setup_panic;
 
-use dezoomify_rs::{dezoomify, Arguments, ZoomError};
+use dezoomify_rs::{
+    describe, dezoomify, dezoomify_batch, dry_run, Arguments, CacheCommand, CacheStore, Command,
+    Event, ProgressMode, ZoomError,
+};
 
 #[tokio::main]
 async fn main() {
@@ -11,6 +17,54 @@ async fn main() {
     let args: Arguments = clap::Parser::parse();
     init_log(&args);
 
+    if let Some(Command::Cache { action }) = &args.command {
+        if let Err(err) = manage_cache(&args, *action) {
+            red_ln!("ERROR {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if args.info {
+        print_info(&args).await;
+        return;
+    }
+
+    if let Some(batch_file) = &args.batch {
+        match dezoomify_batch(&args, batch_file).await {
+            Ok(report) => {
+                green_ln!("{} images successfully downloaded", report.successes);
+                if report.partial_downloads > 0 {
+                    yellow_ln!("{} images partially downloaded", report.partial_downloads);
+                }
+                if report.errors > 0 {
+                    red_ln!("{} images could not be downloaded", report.errors);
+                }
+                if report.partial_downloads + report.errors > 0 {
+                    std::process::exit(1);
+                }
+            }
+            Err(err) => {
+                red_ln!("ERROR {}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(tile_list) = &args.dry_run {
+        match dry_run(&args, tile_list).await {
+            // A tile list written to the standard output is not followed by a message
+            Ok(_) if tile_list.as_os_str() == "-" => {}
+            Ok(count) => green_ln!("Listed {} tiles in {:?}", count, tile_list),
+            Err(err) => {
+                red_ln!("ERROR {}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     loop {
         match dezoomify(&args).await {
             Ok(saved_as) => {
@@ -46,7 +100,76 @@ async fn main() {
     }
 }
 
+async fn print_info(args: &Arguments) {
+    let info = describe(args)
+        .await
+        .and_then(|info| Ok(serde_json::to_string_pretty(&info)?));
+    match info {
+        Ok(json) => println!("{}", json),
+        Err(err) => {
+            red_ln!("ERROR {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn manage_cache(args: &Arguments, action: CacheCommand) -> Result<(), ZoomError> {
+    let root = args
+        .tile_storage_folder
+        .as_ref()
+        .ok_or(ZoomError::MissingTileCache)?;
+    let store = CacheStore::open(root, args.cache_max_size)?;
+    match action {
+        CacheCommand::Ls => {
+            for image in store.images() {
+                let last_used = UNIX_EPOCH + Duration::from_secs(image.last_used);
+                println!(
+                    "{:>7} files {:>10}   last used {}   {}",
+                    image.files,
+                    human_size(image.size),
+                    httpdate::fmt_http_date(last_used),
+                    image.image.as_deref().unwrap_or("(unknown image)")
+                );
+            }
+        }
+        CacheCommand::Gc => {
+            let report = store.gc()?;
+            green_ln!(
+                "Removed {} files ({}). {} files ({}) remain in the cache.",
+                report.removed_files,
+                human_size(report.removed_bytes),
+                report.remaining_files,
+                human_size(report.remaining_bytes)
+            );
+        }
+    }
+    Ok(())
+}
+
+fn human_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024. && unit < units.len() - 1 {
+        size /= 1024.;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, units[unit])
+}
+
 fn init_log(args: &Arguments) {
     let env = env_logger::Env::new().default_filter_or(&args.logging);
-    env_logger::init_from_env(env);
+    let mut builder = env_logger::Builder::from_env(env);
+    if args.progress == ProgressMode::Json {
+        // Log messages share the standard error output with the progress events
+        builder.format(|buf, record| {
+            let event = Event::Log {
+                level: record.level().as_str().to_lowercase(),
+                message: record.args().to_string(),
+            };
+            let json = serde_json::to_string(&event).map_err(std::io::Error::from)?;
+            writeln!(buf, "{}", json)
+        });
+    }
+    builder.init();
 }
@@ -0,0 +1,178 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::dezoomer::TileReference;
+use crate::region::Region;
+use crate::retry::TileFailure;
+use crate::{Vec2d, ZoomError};
+
+/// A record of a download, written next to the output file when some tiles could not be
+/// downloaded. It contains everything needed to resume the download later.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct DownloadManifest {
+    /// The URI the zoom levels were loaded from
+    pub source: String,
+    /// The name of the dezoomer that was used
+    pub dezoomer: String,
+    /// The name of the zoom level that was chosen
+    pub level: String,
+    /// The part of the zoom level that is downloaded, if not the whole image
+    #[serde(default)]
+    pub region: Option<Region>,
+    /// The image file being written
+    pub destination: PathBuf,
+    /// The folder in which downloaded tiles are stored
+    pub tile_cache: Option<PathBuf>,
+    /// The status of every tile that was requested
+    pub tiles: Vec<TileStatus>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct TileStatus {
+    pub x: u32,
+    pub y: u32,
+    pub url: String,
+    pub downloaded: bool,
+    /// Why the tile could not be downloaded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<TileFailure>,
+}
+
+impl DownloadManifest {
+    pub fn new(
+        source: String,
+        dezoomer: String,
+        level: String,
+        region: Option<Region>,
+        destination: PathBuf,
+        tile_cache: Option<PathBuf>,
+    ) -> Self {
+        DownloadManifest {
+            source,
+            dezoomer,
+            level,
+            region,
+            destination,
+            tile_cache,
+            tiles: vec![],
+        }
+    }
+
+    /// The path of the manifest associated to the given output file
+    pub fn path_for(destination: &Path) -> PathBuf {
+        let mut name = destination
+            .file_name()
+            .map(OsString::from)
+            .unwrap_or_default();
+        name.push(".manifest.json");
+        destination.with_file_name(name)
+    }
+
+    pub fn path(&self) -> PathBuf {
+        Self::path_for(&self.destination)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ZoomError> {
+        debug!("Loading download manifest from {:?}", path);
+        let contents = fs::read(path)?;
+        Ok(serde_json::from_slice(&contents)?)
+    }
+
+    /// Write the manifest next to the output file, and return its path
+    pub fn save(&self) -> Result<PathBuf, ZoomError> {
+        let path = self.path();
+        debug!("Writing download manifest to {:?}", path);
+        fs::write(&path, serde_json::to_vec_pretty(self)?)?;
+        Ok(path)
+    }
+
+    /// Remove the manifest of a download that has now fully succeeded
+    pub fn remove(&self) {
+        let path = self.path();
+        if fs::remove_file(&path).is_ok() {
+            debug!("Removed download manifest {:?}", path);
+        }
+    }
+
+    /// Record the results of a batch of tile downloads
+    pub fn add_batch(
+        &mut self,
+        tile_refs: &[TileReference],
+        failed: &HashMap<TileReference, TileFailure>,
+    ) {
+        self.tiles.extend(tile_refs.iter().map(|tile_ref| {
+            let failure = failed.get(tile_ref).copied();
+            TileStatus {
+                x: tile_ref.position.x,
+                y: tile_ref.position.y,
+                url: tile_ref.url.clone(),
+                downloaded: failure.is_none(),
+                failure,
+            }
+        }))
+    }
+
+    /// A short description of why tiles failed, such as "3 × HTTP 404, 1 × timeout"
+    pub fn failure_summary(&self) -> Option<String> {
+        let mut counts: BTreeMap<TileFailure, usize> = BTreeMap::new();
+        for failure in self.tiles.iter().filter_map(|t| t.failure) {
+            *counts.entry(failure).or_default() += 1;
+        }
+        if counts.is_empty() {
+            return None;
+        }
+        let parts: Vec<String> = counts
+            .iter()
+            .map(|(failure, count)| format!("{} × {}", count, failure))
+            .collect();
+        Some(parts.join(", "))
+    }
+
+    pub fn failed_tiles(&self) -> impl Iterator<Item = TileReference> + '_ {
+        self.tiles
+            .iter()
+            .filter(|t| !t.downloaded)
+            .map(|t| TileReference {
+                url: t.url.clone(),
+                position: Vec2d { x: t.x, y: t.y },
+            })
+    }
+}
+
+#[test]
+fn test_manifest_path() {
+    assert_eq!(
+        DownloadManifest::path_for(Path::new("/a/b/image.jpg")),
+        PathBuf::from("/a/b/image.jpg.manifest.json")
+    );
+}
+
+#[test]
+fn test_manifest_roundtrip() {
+    let destination = std::env::temp_dir().join("dezoomify-rs-manifest-test.png");
+    let mut manifest = DownloadManifest::new(
+        "http://example.com/info.json".into(),
+        "auto".into(),
+        "IIIF level".into(),
+        "pct:0,0,50,50".parse().ok(),
+        destination,
+        None,
+    );
+    let tile = |x: u32| TileReference {
+        url: format!("http://example.com/{}.jpg", x),
+        position: Vec2d { x, y: 0 },
+    };
+    let failed = std::iter::once((tile(1), TileFailure::HttpStatus(404))).collect();
+    manifest.add_batch(&[tile(0), tile(1)], &failed);
+    assert_eq!(manifest.failed_tiles().collect::<Vec<_>>(), vec![tile(1)]);
+    assert_eq!(manifest.failure_summary().unwrap(), "1 × HTTP 404");
+    let path = manifest.save().unwrap();
+    assert_eq!(DownloadManifest::load(&path).unwrap(), manifest);
+    manifest.remove();
+    assert!(!path.exists());
+}
@@ -4,16 +4,19 @@ use std::path::PathBuf;
 use std::sync::Arc;
 
 use image::DynamicImage;
-use log::{debug, trace, warn};
-use reqwest::{header, Client};
-use sanitize_filename_reader_friendly::sanitize;
+use log::{debug, trace};
+use reqwest::{header, Client, StatusCode};
 use tokio::fs;
 use tokio::time::Duration;
 use url::Url;
 
 use crate::arguments::Arguments;
+use crate::cache::FileCache;
 use crate::dezoomer::{PostProcessFn, TileReference};
 use crate::errors::BufferToImageError;
+use crate::fetcher::{Fetcher, ReqwestFetcher};
+use crate::retry::TileFailure;
+use crate::throttler::{parse_retry_after, Throttler};
 use crate::tile::Tile;
 use crate::{TileDownloadError, ZoomError};
 
@@ -21,76 +24,158 @@ use crate::{TileDownloadError, ZoomError};
 /// If uri doesnt start with "http(s)://", it is considered to be a path
 /// to a local file
 // TODO: return Bytes
-pub async fn fetch_uri(uri: &str, http: &Client) -> Result<Vec<u8>, ZoomError> {
-    if uri.starts_with("http://") || uri.starts_with("https://") {
-        let req = http.get(uri).build()?;
-        debug!(
-            "Making http request to {uri} with headers '{:?}'",
-            req.headers()
-        );
-        let response = http.execute(req).await?;
+pub async fn fetch_uri(uri: &str, http: &HttpClient) -> Result<Vec<u8>, ZoomError> {
+    let fetched = fetch_uri_if_modified(uri, http, &Validators::default()).await?;
+    Ok(fetched.map(|(contents, _)| contents).unwrap_or_default())
+}
+
+/// The response headers that allow checking whether a previously downloaded file has changed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+
+    fn from_headers(headers: &header::HeaderMap) -> Self {
+        let get = |name| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from)
+        };
+        Validators {
+            etag: get(header::ETAG),
+            last_modified: get(header::LAST_MODIFIED),
+        }
+    }
+}
+
+/// Fetch data like `fetch_uri`. When validators of a previous download are given,
+/// the server is asked to send the data only if it has changed, and None is returned if it has not.
+pub async fn fetch_uri_if_modified(
+    uri: &str,
+    http: &HttpClient,
+    validators: &Validators,
+) -> Result<Option<(Vec<u8>, Validators)>, ZoomError> {
+    if is_remote(uri) {
+        let mut req = http::Request::get(uri);
+        if let Some(etag) = &validators.etag {
+            req = req.header(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            req = req.header(header::IF_MODIFIED_SINCE, last_modified);
+        }
+        let response = http.fetch(req.body(())?).await?;
         debug!(
             "Got http response for {uri}: status={},  headers={:?}",
             response.status(),
             response.headers()
         );
-        let response = response.error_for_status()?;
-        let mut contents = Vec::new();
-        let bytes = response.bytes().await?;
-        contents.extend(bytes);
+        let status = response.status();
+        if status == StatusCode::NOT_MODIFIED && !validators.is_empty() {
+            debug!("'{}' was not modified", uri);
+            return Ok(None);
+        }
+        if status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE {
+            let retry_after = response
+                .headers()
+                .get(header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(parse_retry_after)
+                .unwrap_or_default();
+            return Err(ZoomError::SlowDown {
+                status: status.as_u16(),
+                retry_after_secs: retry_after.as_secs(),
+            });
+        }
+        if status.is_client_error() || status.is_server_error() {
+            return Err(ZoomError::HttpStatus {
+                status: status.as_u16(),
+                uri: uri.to_string(),
+            });
+        }
+        let validators = Validators::from_headers(response.headers());
         trace!("Successfully finished loading url: '{}'", uri);
-        Ok(contents)
+        Ok(Some((response.into_body(), validators)))
     } else {
         debug!("Loading file: '{}'", uri);
         let result = fs::read(uri).await?;
         debug!("Loaded file: '{}'", uri);
-        Ok(result)
+        Ok(Some((result, Validators::default())))
+    }
+}
+
+/// Sends requests through a [Fetcher], adding default headers to them
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    fetcher: Arc<dyn Fetcher>,
+    headers: header::HeaderMap,
+}
+
+impl HttpClient {
+    pub fn new(fetcher: Arc<dyn Fetcher>, headers: header::HeaderMap) -> Self {
+        HttpClient { fetcher, headers }
+    }
+
+    pub async fn fetch(
+        &self,
+        mut request: http::Request<()>,
+    ) -> Result<http::Response<Vec<u8>>, ZoomError> {
+        for (name, value) in &self.headers {
+            if !request.headers().contains_key(name) {
+                request.headers_mut().insert(name, value.clone());
+            }
+        }
+        debug!(
+            "Making http request to {} with headers '{:?}'",
+            request.uri(),
+            request.headers()
+        );
+        self.fetcher.fetch(request).await
+    }
+}
+
+impl From<Client> for HttpClient {
+    fn from(client: Client) -> Self {
+        HttpClient::new(
+            Arc::new(ReqwestFetcher::new(client)),
+            header::HeaderMap::new(),
+        )
     }
 }
 
 pub struct TileDownloader {
-    pub http_client: reqwest::Client,
+    pub http_client: HttpClient,
     pub post_process_fn: PostProcessFn,
-    pub retries: usize,
-    pub retry_delay: Duration,
-    pub tile_storage_folder: Option<PathBuf>,
+    pub cache: FileCache,
+    pub throttler: Throttler,
 }
 
 impl TileDownloader {
+    /// Make a single attempt at downloading a tile.
+    /// Failed tiles are retried later by the caller, so that a tile waiting for its retry
+    /// does not prevent other tiles from being downloaded.
     pub async fn download_tile(
         &self,
         tile_reference: TileReference,
     ) -> Result<Tile, TileDownloadError> {
-        // The initial delay after which a failed request is retried depends on the position of the tile
-        // in order to avoid sending repeated "bursts" of requests to a server that is struggling
-        let n = 100;
-        let idx: f64 = ((tile_reference.position.x + tile_reference.position.y) % n).into();
         let tile_reference = Arc::new(tile_reference);
-        let mut wait_time = self.retry_delay
-            + Duration::from_secs_f64(idx * self.retry_delay.as_secs_f64() / n as f64);
-        let mut failures: usize = 0;
-        loop {
-            match self.load_image(Arc::clone(&tile_reference)).await {
-                Ok(image) => {
-                    return Ok(Tile {
-                        image,
-                        position: tile_reference.position,
-                    })
-                }
-                Err(cause) => {
-                    if failures >= self.retries {
-                        return Err(TileDownloadError {
-                            tile_reference: Arc::try_unwrap(tile_reference)
-                                .expect("tile reference shouldn't leak"),
-                            cause,
-                        });
-                    }
-                    failures += 1;
-                    warn!("{}. Retrying tile download in {:?}.", cause, wait_time);
-                    tokio::time::sleep(wait_time).await;
-                    wait_time *= 2;
-                }
-            }
+        match self.load_image(Arc::clone(&tile_reference)).await {
+            Ok(image) => Ok(Tile {
+                image,
+                position: tile_reference.position,
+            }),
+            Err(cause) => Err(TileDownloadError {
+                tile_reference: Arc::try_unwrap(tile_reference)
+                    .expect("tile reference shouldn't leak"),
+                failure: TileFailure::of(&cause),
+                cause,
+            }),
         }
     }
 
@@ -98,64 +183,57 @@ impl TileDownloader {
         &self,
         tile_reference: Arc<TileReference>,
     ) -> Result<DynamicImage, ZoomError> {
-        let bytes = if let Some(bytes) = self.read_from_tile_cache(&tile_reference.url).await {
-            bytes
-        } else {
-            let bytes = self
-                .download_image_bytes(Arc::clone(&tile_reference))
-                .await?;
-            self.write_to_tile_cache(&tile_reference.url, &bytes).await;
-            bytes
-        };
+        let bytes = self
+            .cache
+            .get_or_download(&tile_reference.url, |validators| {
+                self.download_image_bytes(Arc::clone(&tile_reference), validators)
+            })
+            .await?;
         Ok(tokio::task::spawn_blocking(move || image::load_from_memory(&bytes)).await??)
     }
 
     async fn download_image_bytes(
         &self,
         tile_reference: Arc<TileReference>,
-    ) -> Result<Vec<u8>, ZoomError> {
-        let mut bytes = fetch_uri(&tile_reference.url, &self.http_client).await?;
-        if let PostProcessFn::Fn(post_process) = self.post_process_fn {
+        validators: Validators,
+    ) -> Result<Option<(Vec<u8>, Validators)>, ZoomError> {
+        let permit = self.throttler.acquire(&tile_reference.url).await;
+        let result =
+            fetch_uri_if_modified(&tile_reference.url, &self.http_client, &validators).await;
+        if let Some(permit) = permit {
+            match &result {
+                Ok(_) => permit.success(),
+                Err(ZoomError::SlowDown {
+                    retry_after_secs, ..
+                }) => permit.slow_down(Duration::from_secs(*retry_after_secs)),
+                Err(_) => {}
+            }
+        }
+        let Some((mut bytes, validators)) = result? else {
+            return Ok(None);
+        };
+        if !self.post_process_fn.is_none() {
+            let post_process = self.post_process_fn.clone();
             bytes = tokio::task::spawn_blocking(move || -> Result<_, BufferToImageError> {
-                post_process(&tile_reference, bytes)
+                post_process
+                    .apply(&tile_reference, bytes)
                     .map_err(|e| BufferToImageError::PostProcessing { e })
             })
             .await??;
         }
-        Ok(bytes)
-    }
-
-    async fn write_to_tile_cache(&self, uri: &str, contents: &[u8]) {
-        if let Some(root) = &self.tile_storage_folder {
-            match tokio::fs::write(root.join(sanitize(uri)), contents).await {
-                Ok(_) => debug!("Wrote {} to tile cache ({} bytes)", uri, contents.len()),
-                Err(e) => warn!(
-                    "Unable to write {} to the tile cache {:?}: {}",
-                    uri, root, e
-                ),
-            }
-        }
+        Ok(Some((bytes, validators)))
     }
+}
 
-    async fn read_from_tile_cache(&self, uri: &str) -> Option<Vec<u8>> {
-        if let Some(root) = &self.tile_storage_folder {
-            match tokio::fs::read(root.join(sanitize(uri))).await {
-                Ok(d) => {
-                    debug!("{} read from tile cache", uri);
-                    return Some(d);
-                }
-                Err(e) => debug!("Unable to open {} from tile cache {:?}: {}", uri, root, e),
-            }
-        }
-        None
-    }
+pub(crate) fn is_remote(uri: &str) -> bool {
+    uri.starts_with("http://") || uri.starts_with("https://")
 }
 
 pub fn client<'a, I: Iterator<Item = (&'a String, &'a String)>>(
     headers: I,
     args: &Arguments,
     uri: Option<&str>,
-) -> Result<reqwest::Client, ZoomError> {
+) -> Result<HttpClient, ZoomError> {
     let referer = uri.or(args.input_uri.as_deref()).unwrap_or("");
     let header_map = default_headers()
         .iter()
@@ -168,15 +246,7 @@ pub fn client<'a, I: Iterator<Item = (&'a String, &'a String)>>(
         "Creating an http client with the following headers: {:?}",
         header_map
     );
-    let client = reqwest::Client::builder()
-        .http1_title_case_headers()
-        .default_headers(header_map)
-        .referer(false)
-        .pool_max_idle_per_host(args.max_idle_per_host)
-        .danger_accept_invalid_certs(args.accept_invalid_certs)
-        .timeout(args.timeout)
-        .build()?;
-    Ok(client)
+    Ok(HttpClient::new(args.fetcher()?, header_map))
 }
 
 pub fn default_headers() -> HashMap<String, String> {
@@ -0,0 +1,65 @@
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::arguments::ProgressMode;
+use crate::events::Event;
+use crate::Arguments;
+
+/// Reports the events of the download of a zoom level,
+/// and displays them as a progress bar in the terminal when requested
+pub(crate) struct Progress<'a> {
+    args: &'a Arguments,
+    bar: Option<ProgressBar>,
+}
+
+impl<'a> Progress<'a> {
+    pub fn new(args: &'a Arguments) -> Self {
+        let bar = (args.interactive && args.progress == ProgressMode::Bar).then(|| {
+            let bar = ProgressBar::new(10);
+            bar.set_style(
+                ProgressStyle::default_bar()
+                    .template("[ETA:{eta}] {bar:40.cyan/blue} {pos:>4}/{len:4} {msg}")
+                    .expect("Invalid indicatif progress bar template")
+                    .progress_chars("##-"),
+            );
+            bar.set_message("Computing the URLs of the image tiles...");
+            bar
+        });
+        Progress { args, bar }
+    }
+
+    pub fn emit(&self, event: Event) {
+        if let Some(bar) = &self.bar {
+            render(bar, &event);
+        }
+        self.args.emit(event);
+    }
+}
+
+fn render(bar: &ProgressBar, event: &Event) {
+    match event {
+        Event::BatchStarted { total, .. } => {
+            bar.set_length(*total);
+            bar.set_message("Requesting the tiles...");
+        }
+        Event::RetryScheduled { tiles, delay_ms } => {
+            bar.set_message(format!("Retrying {} tiles in {}ms...", tiles, delay_ms));
+        }
+        Event::TileLoaded { position, .. } => {
+            bar.inc(1);
+            bar.set_message(format!("Loaded tile at {}", position));
+        }
+        Event::TileFailed {
+            will_retry: false,
+            cause,
+            ..
+        } => {
+            bar.inc(1);
+            bar.set_message(cause.clone());
+        }
+        Event::EncodingStarted => {
+            bar.set_message("Downloaded all tiles. Finalizing the image file.");
+        }
+        Event::Finished { .. } => bar.finish_with_message("Finished tile download"),
+        _ => {}
+    }
+}
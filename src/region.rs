@@ -0,0 +1,225 @@
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::dezoomer::TileReference;
+use crate::tile::Tile;
+use crate::Vec2d;
+
+/// A part of an image to download instead of the whole image.
+/// It is expressed either in pixels (`x,y,w,h`)
+/// or in percentages of the image size (`pct:x,y,w,h`), like in IIIF.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Region {
+    Pixels(Rect),
+    Percent { x: f64, y: f64, w: f64, h: f64 },
+}
+
+/// A rectangle, in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rect {
+    pub position: Vec2d,
+    pub size: Vec2d,
+}
+
+impl Region {
+    /// The rectangle covered by this region in an image of the given size.
+    /// Parts of the region that are outside of the image are ignored.
+    pub fn in_image(&self, image_size: Vec2d) -> Rect {
+        let rect = match *self {
+            Region::Pixels(rect) => rect,
+            Region::Percent { x, y, w, h } => {
+                let px = |pct: f64, total: u32| (pct * f64::from(total) / 100.).round() as u32;
+                Rect {
+                    position: Vec2d {
+                        x: px(x, image_size.x),
+                        y: px(y, image_size.y),
+                    },
+                    size: Vec2d {
+                        x: px(w, image_size.x),
+                        y: px(h, image_size.y),
+                    },
+                }
+            }
+        };
+        let position = rect.position.min(image_size);
+        let bottom_right = Vec2d {
+            x: rect.position.x.saturating_add(rect.size.x),
+            y: rect.position.y.saturating_add(rect.size.y),
+        };
+        let size = bottom_right.min(image_size) - position;
+        Rect { position, size }
+    }
+}
+
+impl FromStr for Region {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err_msg = "Invalid region. Expected 'x,y,width,height' in pixels, \
+                       or 'pct:x,y,width,height' in percentages of the image size";
+        if let Some(pct) = s.strip_prefix("pct:") {
+            let values: Vec<f64> = pct
+                .split(',')
+                .map(|v| v.trim().parse().map_err(|_| err_msg))
+                .collect::<Result<_, _>>()?;
+            match values[..] {
+                [x, y, w, h] if values.iter().all(|v| (0. ..=100.).contains(v)) => {
+                    Ok(Region::Percent { x, y, w, h })
+                }
+                _ => Err(err_msg),
+            }
+        } else {
+            let values: Vec<u32> = s
+                .split(',')
+                .map(|v| v.trim().parse().map_err(|_| err_msg))
+                .collect::<Result<_, _>>()?;
+            match values[..] {
+                [x, y, w, h] => Ok(Region::Pixels(Rect {
+                    position: Vec2d { x, y },
+                    size: Vec2d { x: w, y: h },
+                })),
+                _ => Err(err_msg),
+            }
+        }
+    }
+}
+
+impl Rect {
+    pub fn bottom_right(&self) -> Vec2d {
+        self.position + self.size
+    }
+
+    /// Whether this rectangle has pixels in common with the rectangle between the two given points
+    pub fn intersects(&self, top_left: Vec2d, bottom_right: Vec2d) -> bool {
+        let self_bottom_right = self.bottom_right();
+        top_left.x < self_bottom_right.x
+            && top_left.y < self_bottom_right.y
+            && self.position.x < bottom_right.x
+            && self.position.y < bottom_right.y
+    }
+
+    /// Keep only the part of the tile that is inside this rectangle,
+    /// with a position relative to the top left corner of the rectangle
+    pub fn crop_tile(&self, tile: &Tile) -> Option<Tile> {
+        if !self.intersects(tile.position, tile.bottom_right()) {
+            return None;
+        }
+        let top_left = tile.position.max(self.position);
+        let bottom_right = tile.bottom_right().min(self.bottom_right());
+        let crop_position = top_left - tile.position;
+        let crop_size = bottom_right - top_left;
+        Some(Tile {
+            image: tile
+                .image
+                .crop_imm(crop_position.x, crop_position.y, crop_size.x, crop_size.y),
+            position: top_left - self.position,
+        })
+    }
+
+    /// Keep only the tiles that may contain pixels of this rectangle.
+    /// The size of a tile is not known before it is downloaded,
+    /// so each tile is assumed to extend until the beginning of the next one.
+    pub fn filter_tiles(
+        &self,
+        mut tile_refs: Vec<TileReference>,
+        image_size: Vec2d,
+    ) -> Vec<TileReference> {
+        let xs: BTreeSet<u32> = tile_refs.iter().map(|t| t.position.x).collect();
+        let ys: BTreeSet<u32> = tile_refs.iter().map(|t| t.position.y).collect();
+        let next = |starts: &BTreeSet<u32>, start: u32, end: u32| {
+            starts.range(start + 1..).next().copied().unwrap_or(end)
+        };
+        tile_refs.retain(|t| {
+            let bottom_right = Vec2d {
+                x: next(&xs, t.position.x, image_size.x),
+                y: next(&ys, t.position.y, image_size.y),
+            };
+            self.intersects(t.position, bottom_right)
+        });
+        tile_refs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::DynamicImage;
+
+    use super::*;
+
+    fn rect(x: u32, y: u32, w: u32, h: u32) -> Rect {
+        Rect {
+            position: Vec2d { x, y },
+            size: Vec2d { x: w, y: h },
+        }
+    }
+
+    #[test]
+    fn test_parse_region() {
+        assert_eq!(
+            "10,20,30,40".parse::<Region>(),
+            Ok(Region::Pixels(rect(10, 20, 30, 40)))
+        );
+        assert_eq!(
+            "pct:0,12.5,50,100".parse::<Region>(),
+            Ok(Region::Percent {
+                x: 0.,
+                y: 12.5,
+                w: 50.,
+                h: 100.
+            })
+        );
+        assert!("1,2,3".parse::<Region>().is_err());
+        assert!("a,b,c,d".parse::<Region>().is_err());
+        assert!("pct:0,0,150,10".parse::<Region>().is_err());
+        assert!("".parse::<Region>().is_err());
+    }
+
+    #[test]
+    fn test_region_in_image() {
+        let size = Vec2d { x: 200, y: 100 };
+        let pct = Region::Percent {
+            x: 50.,
+            y: 25.,
+            w: 50.,
+            h: 50.,
+        };
+        assert_eq!(pct.in_image(size), rect(100, 25, 100, 50));
+        let overflowing = Region::Pixels(rect(150, 50, 100, 100));
+        assert_eq!(overflowing.in_image(size), rect(150, 50, 50, 50));
+        let huge = Region::Pixels(rect(4294967000, 0, 1000, 10));
+        assert_eq!(huge.in_image(size), rect(200, 0, 0, 10));
+        let huge_size = Region::Pixels(rect(10, 10, u32::MAX, u32::MAX));
+        assert_eq!(huge_size.in_image(size), rect(10, 10, 190, 90));
+    }
+
+    #[test]
+    fn test_filter_tiles() {
+        let tile_refs: Vec<TileReference> = (0..3)
+            .flat_map(|y| (0..3).map(move |x| (x, y)))
+            .map(|(x, y)| TileReference {
+                url: format!("{},{}", x, y),
+                position: Vec2d { x, y } * 10,
+            })
+            .collect();
+        let urls: Vec<String> = rect(15, 5, 10, 4)
+            .filter_tiles(tile_refs, Vec2d { x: 25, y: 25 })
+            .into_iter()
+            .map(|t| t.url)
+            .collect();
+        assert_eq!(urls, vec!["1,0", "2,0"]);
+    }
+
+    #[test]
+    fn test_crop_tile() {
+        let tile = Tile {
+            image: DynamicImage::new_rgb8(10, 10),
+            position: Vec2d { x: 10, y: 10 },
+        };
+        let cropped = rect(15, 0, 100, 12).crop_tile(&tile).unwrap();
+        assert_eq!(cropped.position, Vec2d { x: 0, y: 10 });
+        assert_eq!(cropped.size(), Vec2d { x: 5, y: 2 });
+        assert!(rect(0, 0, 10, 10).crop_tile(&tile).is_none());
+    }
+}
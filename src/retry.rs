@@ -0,0 +1,119 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::BufferToImageError;
+use crate::ZoomError;
+
+/// The reason why a tile could not be downloaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TileFailure {
+    /// The server did not answer in time
+    Timeout,
+    /// The connection to the server failed or was interrupted
+    Connection,
+    /// The server answered with an error status code
+    HttpStatus(u16),
+    /// The tile was downloaded, but is not a valid image
+    Decode,
+    /// The tile could not be decrypted or otherwise processed after its download
+    PostProcessing,
+    /// The tile could not be read from the disk
+    Io,
+    Other,
+}
+
+impl TileFailure {
+    pub fn of(err: &ZoomError) -> Self {
+        match err {
+            ZoomError::Networking { source } => {
+                if source.is_timeout() {
+                    TileFailure::Timeout
+                } else if let Some(status) = source.status() {
+                    TileFailure::HttpStatus(status.as_u16())
+                } else if source.is_connect() || source.is_request() || source.is_body() {
+                    TileFailure::Connection
+                } else {
+                    TileFailure::Other
+                }
+            }
+            ZoomError::HttpStatus { status, .. } | ZoomError::SlowDown { status, .. } => {
+                TileFailure::HttpStatus(*status)
+            }
+            ZoomError::Image { .. }
+            | ZoomError::BufferToImage {
+                source: BufferToImageError::Image { .. },
+            } => TileFailure::Decode,
+            ZoomError::PostProcessing { .. } | ZoomError::BufferToImage { .. } => {
+                TileFailure::PostProcessing
+            }
+            ZoomError::Io { .. } => TileFailure::Io,
+            _ => TileFailure::Other,
+        }
+    }
+}
+
+impl fmt::Display for TileFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TileFailure::Timeout => write!(f, "timeout"),
+            TileFailure::Connection => write!(f, "connection error"),
+            TileFailure::HttpStatus(status) => write!(f, "HTTP {}", status),
+            TileFailure::Decode => write!(f, "invalid image"),
+            TileFailure::PostProcessing => write!(f, "post-processing error"),
+            TileFailure::Io => write!(f, "read error"),
+            TileFailure::Other => write!(f, "other error"),
+        }
+    }
+}
+
+/// Decides which tile failures are worth retrying.
+/// Timeouts, connection errors, 429 and 5xx responses are considered transient,
+/// other failures (such as a missing tile or an invalid image) are permanent,
+/// unless their status code is explicitly listed in `retry_on`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub retry_on: Vec<u16>,
+}
+
+impl RetryPolicy {
+    pub fn should_retry(&self, failure: TileFailure) -> bool {
+        match failure {
+            TileFailure::Timeout | TileFailure::Connection => true,
+            TileFailure::HttpStatus(status) => {
+                status == 429 || status >= 500 || self.retry_on.contains(&status)
+            }
+            _ => false,
+        }
+    }
+}
+
+#[test]
+fn test_retry_policy() {
+    let policy = RetryPolicy {
+        retry_on: vec![403],
+    };
+    assert!(policy.should_retry(TileFailure::Timeout));
+    assert!(policy.should_retry(TileFailure::HttpStatus(503)));
+    assert!(policy.should_retry(TileFailure::HttpStatus(429)));
+    assert!(policy.should_retry(TileFailure::HttpStatus(403)));
+    assert!(!policy.should_retry(TileFailure::HttpStatus(404)));
+    assert!(!policy.should_retry(TileFailure::Decode));
+    assert!(!RetryPolicy::default().should_retry(TileFailure::HttpStatus(403)));
+}
+
+#[test]
+fn test_tile_failure_of() {
+    let err = ZoomError::SlowDown {
+        status: 429,
+        retry_after_secs: 0,
+    };
+    assert_eq!(TileFailure::of(&err), TileFailure::HttpStatus(429));
+    let err: ZoomError = std::io::Error::from(std::io::ErrorKind::NotFound).into();
+    assert_eq!(TileFailure::of(&err), TileFailure::Io);
+    assert_eq!(
+        serde_json::to_string(&TileFailure::HttpStatus(404)).unwrap(),
+        r#"{"http_status":404}"#
+    );
+}
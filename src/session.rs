@@ -0,0 +1,209 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use futures::future::BoxFuture;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::fetcher::Fetcher;
+use crate::ZoomError;
+
+/// A request and its response, as stored in a session archive.
+/// Archives are JSONL files, with one exchange per line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Exchange {
+    pub method: String,
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub request_headers: Vec<(String, String)>,
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub headers: Vec<(String, String)>,
+    /// The body of the response, encoded in base64
+    pub body: String,
+}
+
+/// A fetcher that writes the requests made through another fetcher, and their responses, to an archive
+pub struct Recorder {
+    inner: Arc<dyn Fetcher>,
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, inner: Arc<dyn Fetcher>) -> Result<Self, ZoomError> {
+        debug!("Recording the http session to {:?}", path);
+        let file = Mutex::new(File::create(path)?);
+        Ok(Recorder { inner, file })
+    }
+}
+
+impl Fetcher for Recorder {
+    fn fetch(
+        &self,
+        request: http::Request<()>,
+    ) -> BoxFuture<'_, Result<http::Response<Vec<u8>>, ZoomError>> {
+        Box::pin(async move {
+            let method = request.method().to_string();
+            let url = normalized_url(request.uri());
+            let request_headers = header_pairs(request.headers());
+            let response = self.inner.fetch(request).await?;
+            let exchange = Exchange {
+                method,
+                url,
+                request_headers,
+                status: response.status().as_u16(),
+                headers: header_pairs(response.headers()),
+                body: BASE64_STANDARD.encode(response.body()),
+            };
+            let mut line = serde_json::to_vec(&exchange)?;
+            line.push(b'\n');
+            self.file.lock().unwrap().write_all(&line)?;
+            Ok(response)
+        })
+    }
+}
+
+/// A fetcher that answers the requests with the responses recorded in an archive, without using the network
+pub struct Replayer {
+    /// The recorded exchanges, by method and url, in the order they were made
+    exchanges: Mutex<HashMap<(String, String), VecDeque<Exchange>>>,
+}
+
+impl Replayer {
+    pub fn open(path: &Path) -> Result<Self, ZoomError> {
+        debug!("Replaying the http session from {:?}", path);
+        let mut exchanges: HashMap<_, VecDeque<Exchange>> = HashMap::new();
+        for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let exchange: Exchange =
+                serde_json::from_str(&line).map_err(|source| ZoomError::InvalidSession {
+                    line: i + 1,
+                    source,
+                })?;
+            let key = (exchange.method.clone(), exchange.url.clone());
+            exchanges.entry(key).or_default().push_back(exchange);
+        }
+        let exchanges = Mutex::new(exchanges);
+        Ok(Replayer { exchanges })
+    }
+
+    fn replay(&self, request: &http::Request<()>) -> Result<http::Response<Vec<u8>>, ZoomError> {
+        let key = (request.method().to_string(), normalized_url(request.uri()));
+        let not_recorded = || ZoomError::NotRecorded { uri: key.1.clone() };
+        let exchange = {
+            let mut exchanges = self.exchanges.lock().unwrap();
+            let queue = exchanges.get_mut(&key).filter(|q| !q.is_empty());
+            let queue = queue.ok_or_else(not_recorded)?;
+            // The last response for an url is served again for later requests
+            if queue.len() > 1 {
+                queue.pop_front().unwrap()
+            } else {
+                queue[0].clone()
+            }
+        };
+        debug!("Replaying the response to {} {}", key.0, key.1);
+        let body = BASE64_STANDARD
+            .decode(&exchange.body)
+            .map_err(|_| not_recorded())?;
+        let mut builder = http::Response::builder().status(exchange.status);
+        for (name, value) in &exchange.headers {
+            builder = builder.header(name, value);
+        }
+        Ok(builder.body(body)?)
+    }
+}
+
+impl Fetcher for Replayer {
+    fn fetch(
+        &self,
+        request: http::Request<()>,
+    ) -> BoxFuture<'_, Result<http::Response<Vec<u8>>, ZoomError>> {
+        Box::pin(async move { self.replay(&request) })
+    }
+}
+
+/// The url in the form that reqwest sends it, with special characters percent-encoded
+fn normalized_url(uri: &http::Uri) -> String {
+    let uri = uri.to_string();
+    Url::parse(&uri).map(String::from).unwrap_or(uri)
+}
+
+fn header_pairs(headers: &http::HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = String::from_utf8_lossy(value.as_bytes());
+            (name.to_string(), value.into_owned())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fetcher that answers every request with a 404 error
+    struct NotFound;
+
+    impl Fetcher for NotFound {
+        fn fetch(
+            &self,
+            _request: http::Request<()>,
+        ) -> BoxFuture<'_, Result<http::Response<Vec<u8>>, ZoomError>> {
+            Box::pin(async {
+                let response = http::Response::builder()
+                    .status(404)
+                    .header("X-Test", "yes")
+                    .body(b"missing".to_vec())?;
+                Ok(response)
+            })
+        }
+    }
+
+    fn request(uri: &str) -> http::Request<()> {
+        http::Request::get(uri)
+            .header("Referer", "http://example.com")
+            .body(())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let dir = tempdir::TempDir::new("dezoomify-rs-session").unwrap();
+        let path = dir.path().join("session.jsonl");
+        let uri = "http://example.com/tile_{{X}}.jpg";
+
+        let recorder = Recorder::create(&path, Arc::new(NotFound)).unwrap();
+        let recorded = recorder.fetch(request(uri)).await.unwrap();
+        assert_eq!(recorded.status(), 404);
+        assert_eq!(recorded.body(), b"missing");
+        drop(recorder);
+
+        let archive = std::fs::read_to_string(&path).unwrap();
+        let exchange: Exchange = serde_json::from_str(archive.trim()).unwrap();
+        assert_eq!(exchange.url, "http://example.com/tile_%7B%7BX%7D%7D.jpg");
+        let referer = ("referer".to_string(), "http://example.com".to_string());
+        assert_eq!(exchange.request_headers, vec![referer]);
+
+        let replayer = Replayer::open(&path).unwrap();
+        for _ in 0..2 {
+            let replayed = replayer.fetch(request(uri)).await.unwrap();
+            assert_eq!(replayed.status(), 404);
+            assert_eq!(replayed.headers()["x-test"], "yes");
+            assert_eq!(replayed.body(), b"missing");
+        }
+        let err = replayer
+            .fetch(request("http://example.com/other"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ZoomError::NotRecorded { .. }), "{}", err);
+    }
+}
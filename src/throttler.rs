@@ -1,28 +1,261 @@
-use std::time::{Duration, Instant};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
+use log::{debug, info};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use url::Url;
+
+/// The interval between two requests never grows longer than this
+const MAX_INTERVAL: Duration = Duration::from_secs(60);
+/// The interval between two requests after the first time a server asked us to slow down
+const MIN_SLOWDOWN_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Limits the rate at which requests are sent to each host.
+/// For every host, requests are spaced by at least `min_interval`,
+/// and at most `max_concurrency` of them are running at the same time.
+/// When a server answers that it is overloaded (HTTP 429 or 503),
+/// both limits are tightened for this server, and then progressively relaxed
+/// as long as it keeps answering successfully.
 pub struct Throttler {
-    last_update: Instant,
     min_interval: Duration,
+    max_concurrency: usize,
+    hosts: Mutex<HashMap<String, Arc<HostThrottler>>>,
 }
 
 impl Throttler {
-    pub fn new(min_interval: Duration) -> Self {
+    pub fn new(min_interval: Duration, max_concurrency: usize) -> Self {
         Self {
-            last_update: Instant::now(),
             min_interval,
+            max_concurrency: max_concurrency.max(1),
+            hosts: Mutex::default(),
+        }
+    }
+
+    /// Wait until a request can be sent to the given url.
+    /// Returns None for urls that are not on a remote host, which are not throttled.
+    pub async fn acquire(&self, url: &str) -> Option<ThrottlePermit> {
+        let host = host_key(url)?;
+        let host = Arc::clone(
+            self.hosts
+                .lock()
+                .unwrap()
+                .entry(host)
+                .or_insert_with(|| Arc::new(HostThrottler::new(self))),
+        );
+        let permit = Arc::clone(&host.semaphore)
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed");
+        let wait = {
+            let mut state = host.state.lock().unwrap();
+            let now = Instant::now();
+            let start = state.next_allowed.max(now);
+            state.next_allowed = start + state.interval;
+            start - now
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+        Some(ThrottlePermit {
+            host,
+            permit: Some(permit),
+        })
+    }
+}
+
+struct HostThrottler {
+    min_interval: Duration,
+    max_concurrency: usize,
+    semaphore: Arc<Semaphore>,
+    state: Mutex<HostState>,
+}
+
+struct HostState {
+    /// Current minimum time between two requests
+    interval: Duration,
+    /// When the next request can be sent
+    next_allowed: Instant,
+    /// Current maximum number of simultaneous requests
+    concurrency: usize,
+    /// Permits that have to be removed from the semaphore when they are released
+    excess_permits: usize,
+    /// Successful requests since the last change in concurrency
+    successes: usize,
+}
+
+impl HostThrottler {
+    fn new(throttler: &Throttler) -> Self {
+        HostThrottler {
+            min_interval: throttler.min_interval,
+            max_concurrency: throttler.max_concurrency,
+            semaphore: Arc::new(Semaphore::new(throttler.max_concurrency)),
+            state: Mutex::new(HostState {
+                interval: throttler.min_interval,
+                next_allowed: Instant::now(),
+                concurrency: throttler.max_concurrency,
+                excess_permits: 0,
+                successes: 0,
+            }),
         }
     }
+}
 
-    pub async fn wait(&mut self) {
-        if self.min_interval.is_zero() {
-            return;
+/// The right to send a request to a host.
+/// The result of the request should be reported with `success` or `slow_down`.
+pub struct ThrottlePermit {
+    host: Arc<HostThrottler>,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl ThrottlePermit {
+    /// The server answered normally: slowly go back to the configured limits
+    pub fn success(&self) {
+        let host = &self.host;
+        let mut state = host.state.lock().unwrap();
+        state.interval = host.min_interval.max(state.interval - state.interval / 8);
+        state.successes += 1;
+        if state.successes >= state.concurrency && state.concurrency < host.max_concurrency {
+            state.successes = 0;
+            state.concurrency += 1;
+            if state.excess_permits > 0 {
+                state.excess_permits -= 1;
+            } else {
+                host.semaphore.add_permits(1);
+            }
+            debug!("Increasing concurrency to {}", state.concurrency);
         }
+    }
+
+    /// The server asked us to slow down, and not to send new requests before `retry_after`
+    pub fn slow_down(&self, retry_after: Duration) {
+        let host = &self.host;
+        let mut state = host.state.lock().unwrap();
+        state.interval = (state.interval * 2)
+            .max(MIN_SLOWDOWN_INTERVAL)
+            .min(MAX_INTERVAL);
+        let concurrency = (state.concurrency / 2).max(1);
+        state.excess_permits += state.concurrency - concurrency;
+        state.excess_permits -= host.semaphore.forget_permits(state.excess_permits);
+        state.concurrency = concurrency;
+        state.successes = 0;
         let now = Instant::now();
-        let next_allowed = self.last_update + self.min_interval;
-        self.last_update = now;
-        let sleep_time = next_allowed.saturating_duration_since(now);
-        if !sleep_time.is_zero() {
-            tokio::time::sleep(sleep_time).await;
+        let pause = retry_after.min(MAX_INTERVAL);
+        state.next_allowed = state.next_allowed.max(now + pause);
+        info!(
+            "The server asked to slow down. Now sending at most {} simultaneous requests, \
+             spaced by {:?}, starting in {:?}",
+            state.concurrency,
+            state.interval,
+            state.next_allowed - now
+        );
+    }
+}
+
+impl Drop for ThrottlePermit {
+    fn drop(&mut self) {
+        let mut state = self.host.state.lock().unwrap();
+        if let Some(permit) = self.permit.take() {
+            if state.excess_permits > 0 {
+                state.excess_permits -= 1;
+                permit.forget();
+            }
         }
     }
 }
+
+fn host_key(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let host = url.host_str()?;
+    Some(format!("{}:{}", host, url.port_or_known_default()?))
+}
+
+/// Parse the value of a Retry-After header,
+/// which is either a number of seconds or an http date
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        let in_a_minute = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(61));
+        let parsed = parse_retry_after(&in_a_minute).unwrap();
+        assert!(parsed > Duration::from_secs(50), "{:?}", parsed);
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn test_host_key() {
+        assert_eq!(
+            host_key("https://example.com/a.jpg").as_deref(),
+            Some("example.com:443")
+        );
+        assert_eq!(
+            host_key("http://example.com:8080/a.jpg").as_deref(),
+            Some("example.com:8080")
+        );
+        assert_eq!(host_key("/tmp/a.jpg"), None);
+    }
+
+    #[tokio::test]
+    async fn test_adaptive_concurrency() {
+        let throttler = Throttler::new(Duration::ZERO, 8);
+        let url = "http://example.com/tile.jpg";
+        let permits: Vec<_> = futures::future::join_all((0..8).map(|_| throttler.acquire(url)))
+            .await
+            .into_iter()
+            .map(Option::unwrap)
+            .collect();
+        let host = Arc::clone(&permits[0].host);
+        permits[0].slow_down(Duration::ZERO);
+        assert_eq!(host.state.lock().unwrap().concurrency, 4);
+        assert_eq!(host.state.lock().unwrap().interval, MIN_SLOWDOWN_INTERVAL);
+        drop(permits);
+        assert_eq!(host.semaphore.available_permits(), 4);
+
+        let permit = throttler.acquire(url).await.unwrap();
+        for _ in 0..4 {
+            permit.success();
+        }
+        assert_eq!(host.state.lock().unwrap().concurrency, 5);
+        drop(permit);
+        assert_eq!(host.semaphore.available_permits(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_delays_requests() {
+        let throttler = Throttler::new(Duration::ZERO, 2);
+        let url = "http://example.com/tile.jpg";
+        throttler
+            .acquire(url)
+            .await
+            .unwrap()
+            .slow_down(Duration::from_millis(300));
+        let start = Instant::now();
+        throttler.acquire(url).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(250));
+        let start = Instant::now();
+        throttler
+            .acquire("http://other.com/tile.jpg")
+            .await
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_millis(250));
+    }
+}
@@ -2,7 +2,7 @@ use image::{DynamicImage, GenericImageView};
 
 use crate::dezoomer::{PostProcessFn, TileReference};
 use crate::errors::BufferToImageError;
-use crate::network::fetch_uri;
+use crate::network::{fetch_uri, HttpClient};
 use crate::{Vec2d, ZoomError};
 
 #[derive(Clone)]
@@ -21,19 +21,16 @@ impl Tile {
     pub async fn download(
         post_process_fn: PostProcessFn,
         tile_reference: &TileReference,
-        client: &reqwest::Client,
+        client: &HttpClient,
     ) -> Result<Tile, ZoomError> {
         let bytes = fetch_uri(&tile_reference.url, client).await?;
         let tile_reference = tile_reference.clone();
 
         let tile: Result<Tile, BufferToImageError> = tokio::spawn(async move {
             tokio::task::block_in_place(move || {
-                let transformed_bytes = if let PostProcessFn::Fn(post_process) = post_process_fn {
-                    post_process(&tile_reference, bytes)
-                        .map_err(|e| BufferToImageError::PostProcessing { e })?
-                } else {
-                    bytes
-                };
+                let transformed_bytes = post_process_fn
+                    .apply(&tile_reference, bytes)
+                    .map_err(|e| BufferToImageError::PostProcessing { e })?;
 
                 Ok(Tile {
                     image: image::load_from_memory(&transformed_bytes)?,
@@ -0,0 +1,169 @@
+use std::io::{self, Write};
+
+use custom_error::custom_error;
+
+use crate::dezoomer::*;
+use crate::network::resolve_relative;
+use crate::ZoomError;
+
+/// A dezoomer for lists of tiles in the "x y url" format, with one tile per line,
+/// such as the ones created with --dry-run.
+/// Relative tile urls are resolved from the location of the list,
+/// so a list can reference tiles that were downloaded locally.
+#[derive(Default)]
+pub struct TileListDezoomer;
+
+impl Dezoomer for TileListDezoomer {
+    fn name(&self) -> &'static str {
+        "tile_list"
+    }
+
+    fn zoom_levels(&mut self, data: &DezoomerInput) -> Result<ZoomLevels, DezoomerError> {
+        self.assert(data.uri.ends_with(".tiles"))?;
+        let DezoomerInputWithContents { uri, contents } = data.with_contents()?;
+        let level = parse_tile_list(uri, contents)?;
+        single_level(level)
+    }
+}
+
+custom_error! {pub TileListError
+    NotText{source: std::str::Utf8Error} = "The tile list is not a text file: {source}",
+    MalformedLine{line: usize, source: ZoomError} = "Invalid tile list at line {line}: {source}",
+    MalformedSize{line: usize} = "Invalid image size at line {line}: expected '# size width height'",
+    NoTiles = "The tile list does not contain any tile",
+}
+
+impl From<TileListError> for DezoomerError {
+    fn from(err: TileListError) -> Self {
+        DezoomerError::Other { source: err.into() }
+    }
+}
+
+const SIZE_PREFIX: &str = "# size ";
+
+fn parse_tile_list(uri: &str, contents: &[u8]) -> Result<TileList, TileListError> {
+    let mut size = None;
+    let mut tiles = vec![];
+    for (i, line) in std::str::from_utf8(contents)?.lines().enumerate() {
+        let line = line.trim();
+        if let Some(size_str) = line.strip_prefix(SIZE_PREFIX) {
+            let dimensions: Vec<u32> = size_str
+                .split_whitespace()
+                .filter_map(|d| d.parse().ok())
+                .collect();
+            if let [x, y] = dimensions[..] {
+                size = Some(Vec2d { x, y });
+            } else {
+                return Err(TileListError::MalformedSize { line: i + 1 });
+            }
+        } else if !line.is_empty() && !line.starts_with('#') {
+            let mut tile: TileReference =
+                line.parse()
+                    .map_err(|source| TileListError::MalformedLine {
+                        line: i + 1,
+                        source,
+                    })?;
+            tile.url = resolve_relative(uri, &tile.url);
+            tiles.push(tile);
+        }
+    }
+    if tiles.is_empty() {
+        return Err(TileListError::NoTiles);
+    }
+    Ok(TileList { tiles, size })
+}
+
+/// Write the beginning of a tile list
+pub fn write_header<W: Write>(writer: &mut W, name: &str, size: Option<Vec2d>) -> io::Result<()> {
+    writeln!(writer, "# {}", name)?;
+    if let Some(Vec2d { x, y }) = size {
+        writeln!(writer, "{}{} {}", SIZE_PREFIX, x, y)?;
+    }
+    Ok(())
+}
+
+/// Write a single tile reference to a tile list
+pub fn write_tile<W: Write>(writer: &mut W, tile: &TileReference) -> io::Result<()> {
+    writeln!(
+        writer,
+        "{} {} {}",
+        tile.position.x, tile.position.y, tile.url
+    )
+}
+
+struct TileList {
+    tiles: Vec<TileReference>,
+    size: Option<Vec2d>,
+}
+
+impl TileProvider for TileList {
+    fn next_tiles(&mut self, previous: Option<TileFetchResult>) -> Vec<TileReference> {
+        if previous.is_some() {
+            return vec![];
+        }
+        std::mem::take(&mut self.tiles)
+    }
+
+    fn size_hint(&self) -> Option<Vec2d> {
+        self.size
+    }
+
+    fn tile_count_hint(&self) -> Option<u64> {
+        Some(self.tiles.len() as u64)
+    }
+}
+
+impl std::fmt::Debug for TileList {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Tile list")
+    }
+}
+
+#[test]
+fn test_tile_list_roundtrip() {
+    let tiles = vec![
+        TileReference {
+            url: "http://example.com/0_0.jpg".into(),
+            position: Vec2d { x: 0, y: 0 },
+        },
+        TileReference {
+            url: "http://example.com/1_0.jpg".into(),
+            position: Vec2d { x: 256, y: 0 },
+        },
+    ];
+    let mut contents = vec![];
+    write_header(&mut contents, "Test image", Some(Vec2d { x: 300, y: 200 })).unwrap();
+    for tile in &tiles {
+        write_tile(&mut contents, tile).unwrap();
+    }
+    let mut list = parse_tile_list("/tmp/test.tiles", &contents).unwrap();
+    assert_eq!(list.size_hint(), Some(Vec2d { x: 300, y: 200 }));
+    assert_eq!(list.next_tiles(None), tiles);
+}
+
+#[test]
+fn test_tile_list_relative_urls() {
+    let contents = b"0 0 tiles/0_0.jpg\n\n10 0 tiles/1_0.jpg\n";
+    let mut list = parse_tile_list("http://example.com/a/img.tiles", contents).unwrap();
+    assert_eq!(list.size_hint(), None);
+    let urls: Vec<String> = list.next_tiles(None).into_iter().map(|t| t.url).collect();
+    assert_eq!(
+        urls,
+        vec![
+            "http://example.com/a/tiles/0_0.jpg",
+            "http://example.com/a/tiles/1_0.jpg"
+        ]
+    );
+}
+
+#[test]
+fn test_tile_list_malformed() {
+    let err = parse_tile_list("x.tiles", b"0 0 a.jpg\n0 b.jpg")
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("line 2"), "{}", err);
+    let err = parse_tile_list("x.tiles", b"# size 10\n0 0 a.jpg")
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("line 1"), "{}", err);
+}
@@ -1,6 +1,8 @@
 use std::ops::{Add, Div, Mul, Sub};
 
-#[derive(Debug, PartialEq, Eq, Hash, Default, Clone, Copy)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Eq, Hash, Default, Clone, Copy, Serialize, Deserialize)]
 pub struct Vec2d {
     pub x: u32,
     pub y: u32,
@@ -26,8 +28,8 @@ impl Vec2d {
     }
     pub fn ceil_div<T: Into<Vec2d>>(self, other: T) -> Vec2d {
         let other = other.into();
-        let x: u32 = self.x / other.x + (self.x % other.x != 0) as u32;
-        let y: u32 = self.y / other.y + (self.y % other.y != 0) as u32;
+        let x: u32 = self.x / other.x + !self.x.is_multiple_of(other.x) as u32;
+        let y: u32 = self.y / other.y + !self.y.is_multiple_of(other.y) as u32;
         Vec2d { x, y }
     }
 
@@ -3,10 +3,12 @@ use std::default::Default;
 use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
 
+use futures::future::BoxFuture;
+
 use image::{self, DynamicImage, GenericImageView};
 use image_hasher::HasherConfig;
 
-use dezoomify_rs::{dezoomify, Arguments, ZoomError};
+use dezoomify_rs::{dezoomify, Arguments, Fetcher, ZoomError};
 
 /// Dezoom a file locally
 #[tokio::test(flavor = "multi_thread")]
@@ -29,6 +31,228 @@ pub async fn local_generic_tiles() {
     .unwrap()
 }
 
+#[tokio::test(flavor = "multi_thread")]
+#[allow(clippy::field_reassign_with_default)]
+pub async fn local_tile_list() {
+    use dezoomify_rs::dry_run;
+    // The tile urls in the list are relative to the list itself, so we use an absolute path
+    let input = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("testdata/zoomify/test_custom_size/ImageProperties.xml");
+    let mut args: Arguments = Default::default();
+    args.input_uri = Some(input.to_string_lossy().to_string());
+    args.largest = true;
+    let tile_list = std::env::temp_dir().join("dezoomify-rs-test.tiles");
+    assert_eq!(dry_run(&args, &tile_list).await.unwrap(), 63);
+    test_image(
+        tile_list.to_str().unwrap(),
+        "testdata/zoomify/test_custom_size/expected_result.jpg",
+    )
+    .await
+    .unwrap();
+    std::fs::remove_file(tile_list).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[allow(clippy::field_reassign_with_default)]
+pub async fn dry_run_region_and_unknown_layout() {
+    use dezoomify_rs::dry_run;
+    let tile_list = std::env::temp_dir().join("dezoomify-rs-test-region.tiles");
+    let mut args: Arguments = Default::default();
+    args.input_uri = Some("testdata/zoomify/test_custom_size/ImageProperties.xml".into());
+    args.largest = true;
+    args.region = Some("0,0,10,10".parse().unwrap());
+    assert_eq!(dry_run(&args, &tile_list).await.unwrap(), 1);
+    std::fs::remove_file(&tile_list).unwrap();
+
+    // The generic dezoomer only finds the tiles by downloading them
+    args.input_uri = Some("testdata/generic/map_{{X}}_{{Y}}.jpg".into());
+    args.region = None;
+    let err = dry_run(&args, &tile_list).await.unwrap_err();
+    assert_eq!(err.kind(), "dry_run_unsupported", "{}", err);
+    assert!(!tile_list.exists());
+}
+
+/// Dezoom an image from the http session recorded in an archive, without using the network
+#[tokio::test(flavor = "multi_thread")]
+#[allow(clippy::field_reassign_with_default)]
+pub async fn replayed_generic_tiles() {
+    let expected = "testdata/generic/map_expected.png";
+    let mut args: Arguments = Default::default();
+    args.input_uri = Some("http://127.0.0.1:8765/map_{{X}}_{{Y}}.jpg".into());
+    args.replay = Some("testdata/sessions/generic_map.jsonl".into());
+    args.retries = 0;
+    args.logging = "error".into();
+    let tmp_file = TmpFile("replayed_generic_tiles.png");
+    args.outfile = Some(tmp_file.to_path_buf());
+    dezoomify(&args).await.expect("Dezooming failed");
+    let actual = image::open(tmp_file.to_path_buf()).unwrap();
+    assert_images_equal(actual, image::open(expected).unwrap());
+}
+
+/// A fetcher that serves the files of the testdata folder from http://testdata/
+// Unused in benchmarks
+#[allow(dead_code)]
+struct TestdataFetcher;
+
+impl Fetcher for TestdataFetcher {
+    fn fetch(
+        &self,
+        request: http::Request<()>,
+    ) -> BoxFuture<'_, Result<http::Response<Vec<u8>>, ZoomError>> {
+        Box::pin(async move {
+            let path = request.uri().path().trim_start_matches('/');
+            let response = match std::fs::read(Path::new("testdata").join(path)) {
+                Ok(contents) => http::Response::builder().status(200).body(contents),
+                Err(_) => http::Response::builder().status(404).body(vec![]),
+            };
+            Ok(response.unwrap())
+        })
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[allow(clippy::field_reassign_with_default)]
+pub async fn custom_fetcher() {
+    let expected = "testdata/generic/map_expected.png";
+    let mut args: Arguments = Default::default();
+    args.input_uri = Some("http://testdata/generic/map_{{X}}_{{Y}}.jpg".into());
+    args.fetcher = Some(std::sync::Arc::new(TestdataFetcher));
+    args.retries = 0;
+    args.logging = "error".into();
+    let tmp_file = TmpFile("custom_fetcher.png");
+    args.outfile = Some(tmp_file.to_path_buf());
+    dezoomify(&args).await.expect("Dezooming failed");
+    let actual = image::open(tmp_file.to_path_buf()).unwrap();
+    assert_images_equal(actual, image::open(expected).unwrap());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn builder_job() {
+    use dezoomify_rs::{DezoomifyBuilder, Event, LevelSelection};
+    use std::sync::{Arc, Mutex};
+
+    let expected = "testdata/zoomify/test_custom_size/expected_result.jpg";
+    let tmp_file = TmpFile("builder_job.jpg");
+    let events = Arc::new(Mutex::new(vec![]));
+    let recorded = Arc::clone(&events);
+    let job = DezoomifyBuilder::new("testdata/zoomify/test_custom_size/ImageProperties.xml")
+        .output_file(tmp_file.to_path_buf())
+        .retries(0)
+        .on_event(move |event| recorded.lock().unwrap().push(event.clone()))
+        .build();
+    let saved_as = job.run().await.unwrap();
+    let actual = image::open(saved_as).unwrap();
+    assert_images_equal(actual, image::open(expected).unwrap());
+
+    let events = events.lock().unwrap().clone();
+    // The dezoomer that was chosen automatically is reported
+    assert!(matches!(&events[0], Event::LevelsFound { dezoomer, .. } if dezoomer == "zoomify"));
+    assert!(matches!(events[1], Event::LevelChosen { .. }));
+    let loaded = events
+        .iter()
+        .filter(|e| matches!(e, Event::TileLoaded { .. }))
+        .count();
+    assert_eq!(loaded, 63);
+    assert!(matches!(events.last(), Some(Event::Finished { .. })));
+    let info = job.describe().await.unwrap();
+    assert_eq!(info.dezoomer, "zoomify");
+    assert_eq!(info.levels.len(), 4);
+
+    // Levels that do not fit are never asked interactively
+    let too_small = DezoomifyBuilder::new("testdata/zoomify/test_custom_size/ImageProperties.xml")
+        .level(LevelSelection::Fit {
+            max_width: Some(1),
+            max_height: None,
+        })
+        .build();
+    let err = too_small.run().await.unwrap_err();
+    assert!(matches!(err, ZoomError::NoMatchingLevel { .. }), "{}", err);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn failed_tile_events() {
+    use dezoomify_rs::{DezoomifyBuilder, Event};
+    use std::sync::{Arc, Mutex};
+
+    let dir = std::env::temp_dir().join("dezoomify-rs-failed-tile-events");
+    std::fs::create_dir_all(&dir).unwrap();
+    let existing = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/generic/map_0_0.jpg");
+    let tile_list = dir.join("image.tiles");
+    let contents = format!(
+        "# size 512 256\n0 0 {}\n256 0 missing.jpg\n",
+        existing.display()
+    );
+    std::fs::write(&tile_list, contents).unwrap();
+    let tmp_file = TmpFile("failed_tile_events.png");
+    let events = Arc::new(Mutex::new(vec![]));
+    let recorded = Arc::clone(&events);
+    let job = DezoomifyBuilder::new(tile_list.to_str().unwrap())
+        .output_file(tmp_file.to_path_buf())
+        .retries(0)
+        .on_event(move |event| recorded.lock().unwrap().push(event.clone()))
+        .build();
+    let err = job.run().await.unwrap_err();
+    assert!(matches!(err, ZoomError::PartialDownload { .. }), "{}", err);
+
+    let events: Vec<serde_json::Value> = events
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|event: &Event| serde_json::to_value(event).unwrap())
+        .collect();
+    let names: Vec<&str> = events
+        .iter()
+        .map(|e| e["event"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        names[..3],
+        ["levels_found", "level_chosen", "batch_started"]
+    );
+    let mut tile_events = names[3..5].to_vec();
+    tile_events.sort_unstable();
+    assert_eq!(tile_events, ["tile_failed", "tile_loaded"]);
+    assert_eq!(names[5..], ["encoding_started", "finished"]);
+
+    let failed = events.iter().find(|e| e["event"] == "tile_failed").unwrap();
+    assert!(failed["url"].as_str().unwrap().ends_with("missing.jpg"));
+    assert!(!failed["cause"].as_str().unwrap().is_empty());
+    assert_eq!(failed["will_retry"], false);
+    assert_eq!(failed["total"], 2);
+    let finished = events.last().unwrap();
+    assert_eq!(finished["successful_tiles"], 1);
+    assert_eq!(finished["total_tiles"], 2);
+
+    // The tiles that could be downloaded are still returned
+    let (image, partial) = job.run_to_image().await.unwrap();
+    assert!(matches!(partial, Some(ZoomError::PartialDownload { .. })));
+    assert_eq!(image.dimensions(), (512, 256));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn in_memory_output() {
+    use dezoomify_rs::{DezoomifyBuilder, Output, PixelWriter};
+
+    let job = DezoomifyBuilder::new("testdata/generic/map_{{X}}_{{Y}}.jpg")
+        .retries(0)
+        .build();
+    let (actual, partial) = job.run_to_image().await.unwrap();
+    assert!(partial.is_none());
+    let expected = image::open("testdata/generic/map_expected.png").unwrap();
+    assert_images_equal(actual.clone(), expected);
+
+    let tmp_file = TmpFile("in_memory_output.rgb");
+    let pixels_path = tmp_file.to_path_buf();
+    let area = actual.width() * actual.height();
+    let output = PixelWriter::new(move |size| {
+        assert_eq!(size.x * size.y, area);
+        Ok(Box::new(std::fs::File::create(&pixels_path)?))
+    });
+    job.run_to(Output::Pixels(output)).await.unwrap();
+    let pixels = std::fs::read(tmp_file.to_path_buf()).unwrap();
+    assert_eq!(pixels, actual.to_rgb8().into_raw());
+}
+
 #[allow(clippy::needless_lifetimes)]
 #[allow(clippy::field_reassign_with_default)]
 pub async fn dezoom_image<'a>(input: &str, expected: &'a str) -> Result<TmpFile<'a>, ZoomError> {